@@ -0,0 +1,292 @@
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use clap::{Args, ValueEnum};
+use serde::Serialize;
+use serde_json::{json, Value};
+use tower_lsp::lsp_types::{
+    Diagnostic, DiagnosticSeverity, NumberOrString, Url,
+};
+
+use crate::document::{lint, Workspace};
+
+#[derive(Debug, Args)]
+pub struct CheckArgs {
+    /// Markdown files or directories to check.
+    #[arg(default_value = ".")]
+    paths: Vec<PathBuf>,
+    #[arg(long, value_enum, default_value_t = Format::Text)]
+    format: Format,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+enum Format {
+    /// `file:line:col: severity: message [rule]` lines.
+    Text,
+    /// A JSON array of diagnostics with their file.
+    Json,
+    /// A SARIF 2.1.0 log, for code scanning tools.
+    Sarif,
+}
+
+/// A diagnostic of a checked file.
+#[derive(Debug, Serialize)]
+struct Finding {
+    path: String,
+    #[serde(flatten)]
+    diagnostic: Diagnostic,
+}
+
+pub fn check(args: CheckArgs) -> anyhow::Result<ExitCode> {
+    let findings = findings(&args.paths)?;
+    match args.format {
+        Format::Text => {
+            for it in &findings {
+                println!("{}", text_line(it));
+            }
+        },
+        Format::Json => {
+            println!("{}", serde_json::to_string_pretty(&findings)?)
+        },
+        Format::Sarif => {
+            let cwd = std::env::current_dir()?;
+            let root = Url::from_directory_path(&cwd)
+                .map_err(|_| anyhow::anyhow!("invalid path: {:?}", cwd))?;
+            let log = sarif(&findings, &root);
+            println!("{}", serde_json::to_string_pretty(&log)?)
+        },
+    }
+
+    let errors = findings
+        .iter()
+        .filter(|it| it.diagnostic.severity == Some(DiagnosticSeverity::ERROR))
+        .count();
+    if errors > 0 {
+        eprintln!("{} errors in {} findings", errors, findings.len());
+        return Ok(ExitCode::FAILURE);
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Lint every markdown file under the paths. Links are resolved against all
+/// of them.
+fn findings(paths: &[PathBuf]) -> anyhow::Result<Vec<Finding>> {
+    let paths = paths
+        .iter()
+        .map(|it| it.canonicalize())
+        .collect::<Result<Vec<_>, _>>()?;
+    let (dirs, mut files): (Vec<PathBuf>, Vec<PathBuf>) =
+        paths.into_iter().partition(|it| it.is_dir());
+    let workspace = Workspace::default();
+    workspace.set_roots(dirs);
+    let (found, errors) = workspace.files();
+    files.extend(found);
+    files.sort();
+    files.dedup();
+
+    let cwd = std::env::current_dir()?;
+    let name = |path: &Path| {
+        path.strip_prefix(&cwd)
+            .unwrap_or(path)
+            .display()
+            .to_string()
+    };
+    let mut ret: Vec<Finding> = errors
+        .into_iter()
+        .map(|(path, err)| unreadable(name(&path), &err))
+        .collect();
+    files.retain(|path| match workspace.index_file(path) {
+        Ok(()) => true,
+        Err(err) => {
+            ret.push(unreadable(name(path), &err));
+            false
+        },
+    });
+
+    for path in files {
+        let uri = Url::from_file_path(&path)
+            .map_err(|_| anyhow::anyhow!("invalid path: {:?}", path))?;
+        let name = name(&path);
+        ret.extend(lint(&workspace, &uri).into_iter().map(|diagnostic| {
+            Finding {
+                path: name.clone(),
+                diagnostic,
+            }
+        }));
+    }
+    Ok(ret)
+}
+
+/// An error finding at the start of a file or directory that could not be
+/// read.
+fn unreadable(path: String, err: &dyn std::fmt::Display) -> Finding {
+    Finding {
+        path,
+        diagnostic: Diagnostic {
+            severity: Some(DiagnosticSeverity::ERROR),
+            code: Some(NumberOrString::String("unreadable".to_string())),
+            source: Some("lsp-md".to_string()),
+            message: err.to_string(),
+            ..Default::default()
+        },
+    }
+}
+
+fn text_line(it: &Finding) -> String {
+    let start = it.diagnostic.range.start;
+    format!(
+        "{}:{}:{}: {}: {}{}",
+        it.path,
+        start.line + 1,
+        start.character + 1,
+        severity(&it.diagnostic),
+        it.diagnostic.message,
+        rule(&it.diagnostic)
+            .map(|it| format!(" [{}]", it))
+            .unwrap_or_default()
+    )
+}
+
+fn severity(diagnostic: &Diagnostic) -> &'static str {
+    match diagnostic.severity {
+        Some(DiagnosticSeverity::ERROR) => "error",
+        Some(DiagnosticSeverity::WARNING) => "warning",
+        _ => "note",
+    }
+}
+
+fn rule(diagnostic: &Diagnostic) -> Option<&str> {
+    match &diagnostic.code {
+        Some(NumberOrString::String(it)) => Some(it),
+        _ => None,
+    }
+}
+
+/// SARIF artifact location of a finding path: relative to `%SRCROOT%`, the
+/// working directory, or an absolute file uri for paths outside it.
+fn artifact_location(path: &str, root: &Url) -> Value {
+    let uri = root
+        .to_file_path()
+        .and_then(|it| Url::from_file_path(it.join(path)));
+    match uri {
+        Ok(uri) if Path::new(path).is_absolute() => json!({ "uri": uri }),
+        Ok(uri) => json!({
+            "uri": root.make_relative(&uri).unwrap_or_else(|| uri.to_string()),
+            "uriBaseId": "%SRCROOT%",
+        }),
+        Err(_) => json!({ "uri": path }),
+    }
+}
+
+fn sarif(findings: &[Finding], root: &Url) -> Value {
+    let mut rules: Vec<&str> = findings
+        .iter()
+        .filter_map(|it| rule(&it.diagnostic))
+        .collect();
+    rules.sort();
+    rules.dedup();
+    let results: Vec<Value> = findings
+        .iter()
+        .map(|it| {
+            let range = it.diagnostic.range;
+            json!({
+                "ruleId": rule(&it.diagnostic),
+                "level": severity(&it.diagnostic),
+                "message": { "text": it.diagnostic.message },
+                "locations": [{
+                    "physicalLocation": {
+                        "artifactLocation": artifact_location(&it.path, root),
+                        "region": {
+                            "startLine": range.start.line + 1,
+                            "startColumn": range.start.character + 1,
+                            "endLine": range.end.line + 1,
+                            "endColumn": range.end.character + 1,
+                        },
+                    },
+                }],
+            })
+        })
+        .collect();
+    json!({
+        "version": "2.1.0",
+        "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+        "runs": [{
+            "tool": {
+                "driver": {
+                    "name": "lsp-md",
+                    "version": env!("CARGO_PKG_VERSION"),
+                    "rules": rules
+                        .into_iter()
+                        .map(|it| json!({ "id": it }))
+                        .collect::<Vec<_>>(),
+                },
+            },
+            "originalUriBaseIds": {
+                "%SRCROOT%": { "uri": root },
+            },
+            "results": results,
+        }],
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use tower_lsp::lsp_types::{Position, Range};
+
+    use super::*;
+
+    #[test]
+    fn findings_should_print_like_a_compiler() -> anyhow::Result<()> {
+        let it = Finding {
+            path: "notes/a.md".to_string(),
+            diagnostic: Diagnostic {
+                range: Range::new(Position::new(2, 4), Position::new(2, 12)),
+                severity: Some(DiagnosticSeverity::ERROR),
+                code: Some(NumberOrString::String("broken-link".to_string())),
+                message: "b.md does not exist".to_string(),
+                ..Default::default()
+            },
+        };
+        assert_eq!(
+            "notes/a.md:3:5: error: b.md does not exist [broken-link]",
+            text_line(&it)
+        );
+
+        let root = Url::parse("file:///work/")?;
+        let log = sarif(&[it], &root);
+        let result = &log["runs"][0]["results"][0];
+        assert_eq!("broken-link", result["ruleId"]);
+        assert_eq!("error", result["level"]);
+        assert_eq!(
+            json!({
+                "startLine": 3,
+                "startColumn": 5,
+                "endLine": 3,
+                "endColumn": 13,
+            }),
+            result["locations"][0]["physicalLocation"]["region"]
+        );
+
+        Ok(())
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn sarif_should_use_valid_uris() -> anyhow::Result<()> {
+        let root = Url::parse("file:///work/")?;
+        assert_eq!(
+            json!({ "uri": "notes/a.md", "uriBaseId": "%SRCROOT%" }),
+            artifact_location("notes/a.md", &root)
+        );
+        assert_eq!(
+            json!({ "uri": "file:///notes/my%20notes.md" }),
+            artifact_location("/notes/my notes.md", &root)
+        );
+        assert_eq!(
+            json!({ "uri": "my%20notes.md", "uriBaseId": "%SRCROOT%" }),
+            artifact_location("my notes.md", &root)
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,129 @@
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use clap::Args;
+use similar::TextDiff;
+
+use crate::document::{CodeFormatter, Document, Workspace};
+use crate::settings::{read_config_file, SettingsLayers};
+
+#[derive(Debug, Args)]
+pub struct FmtArgs {
+    /// Markdown files or directories to format, `-` or none for stdin.
+    paths: Vec<PathBuf>,
+    /// Print a diff of unformatted files and exit with 1 instead of writing
+    /// them.
+    #[arg(long)]
+    check: bool,
+    /// Line width, `wrapWidth` of `./.lsp-md.toml` or 80 by default.
+    #[arg(long)]
+    wrap_width: Option<usize>,
+}
+
+pub fn fmt(args: FmtArgs) -> anyhow::Result<ExitCode> {
+    let width = match args.wrap_width {
+        Some(it) => it,
+        None => {
+            SettingsLayers {
+                file: read_config_file(&[Path::new(".")])?,
+                ..Default::default()
+            }
+            .settings()?
+            .wrap_width
+        },
+    };
+
+    if args.paths.is_empty() || args.paths == [Path::new("-")] {
+        let mut text = String::new();
+        std::io::stdin().read_to_string(&mut text)?;
+        let formatted = format_text(&text, width)?;
+        if !args.check {
+            std::io::stdout().write_all(formatted.as_bytes())?;
+            return Ok(ExitCode::SUCCESS);
+        }
+        if formatted == text {
+            return Ok(ExitCode::SUCCESS);
+        }
+        print!("{}", diff("<stdin>", &text, &formatted));
+        return Ok(ExitCode::FAILURE);
+    }
+
+    let mut unformatted = 0;
+    let (files, errors) = files(&args.paths);
+    let mut failed = errors.len();
+    for (path, err) in errors {
+        eprintln!("skipped {}: {}", path.display(), err);
+    }
+    for path in files {
+        let text = match std::fs::read_to_string(&path) {
+            Ok(it) => it,
+            Err(err) => {
+                eprintln!("failed to read {}: {}", path.display(), err);
+                failed += 1;
+                continue;
+            },
+        };
+        let formatted = format_text(&text, width)?;
+        if formatted == text {
+            continue;
+        }
+        if args.check {
+            print!("{}", diff(&path.display().to_string(), &text, &formatted));
+            unformatted += 1;
+        } else {
+            std::fs::write(&path, formatted)?;
+        }
+    }
+    if unformatted > 0 {
+        eprintln!("{} files are not formatted", unformatted);
+    }
+    if unformatted > 0 || failed > 0 {
+        return Ok(ExitCode::FAILURE);
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Files as given, and markdown files under directories, with the
+/// directories that could not be read.
+fn files(paths: &[PathBuf]) -> (Vec<PathBuf>, Vec<(PathBuf, io::Error)>) {
+    let (dirs, mut ret): (Vec<PathBuf>, Vec<PathBuf>) =
+        paths.iter().cloned().partition(|it| it.is_dir());
+    let workspace = Workspace::default();
+    workspace.set_roots(dirs);
+    let (files, errors) = workspace.files();
+    ret.extend(files);
+    (ret, errors)
+}
+
+fn format_text(text: &str, width: usize) -> anyhow::Result<String> {
+    let doc = Document::parse(text)?;
+    Ok(CodeFormatter::new(&doc).wrap_width(width).format_all())
+}
+
+fn diff(name: &str, old: &str, new: &str) -> String {
+    TextDiff::from_lines(old, new)
+        .unified_diff()
+        .header(name, name)
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_text_should_wrap_paragraphs_and_lists() -> anyhow::Result<()> {
+        let src = "# Title\n\none two three four five\n\n- six seven eight \
+                   nine\n\n```\nkeep this code block as is\n```\n\n> quoted \
+                   one two three\n> four five six seven\n";
+        assert_eq!(
+            "# Title\n\none two three four\nfive\n\n- six seven eight\n  \
+             nine\n\n```\nkeep this code block as is\n```\n\n> quoted one \
+             two three\n> four five six seven\n",
+            format_text(src, 20)?
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,112 @@
+mod check;
+mod fmt;
+mod query;
+
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::str::FromStr;
+
+pub use check::CheckArgs;
+use clap::{Parser, Subcommand};
+pub use fmt::FmtArgs;
+pub use query::{IndexArgs, SearchArgs, SimilarArgs};
+
+/// Markdown language server. Serves LSP over stdio unless a command is
+/// given.
+#[derive(Debug, Parser)]
+#[command(version, about, args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    /// Serve on `tcp:HOST:PORT` or `unix:PATH` instead of stdio. Connections
+    /// share the loaded model and indexes.
+    #[arg(long, value_name = "ADDRESS")]
+    pub listen: Option<Listen>,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+/// Socket a server listens on.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Listen {
+    Tcp(String),
+    Unix(PathBuf),
+}
+
+impl FromStr for Listen {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("tcp", addr)) if !addr.is_empty() => {
+                Ok(Listen::Tcp(addr.to_string()))
+            },
+            Some(("unix", path)) if !path.is_empty() => {
+                Ok(Listen::Unix(PathBuf::from(path)))
+            },
+            _ => Err(anyhow::anyhow!(
+                "expected tcp:HOST:PORT or unix:PATH, got {}",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Subcommand)]
+pub enum Command {
+    /// Format markdown files in place, or stdin to stdout.
+    Fmt(FmtArgs),
+    /// Build or update the embedding cache of a workspace.
+    Index(IndexArgs),
+    /// Sections most similar to a section.
+    Similar(SimilarArgs),
+    /// Sections matching a query by meaning and keywords.
+    Search(SearchArgs),
+    /// Report broken links and anchors and structure problems, exiting with 1
+    /// on errors.
+    Check(CheckArgs),
+}
+
+impl Command {
+    pub fn run(self) -> anyhow::Result<ExitCode> {
+        match self {
+            Command::Fmt(args) => fmt::fmt(args),
+            Command::Index(args) => query::index(args),
+            Command::Similar(args) => query::similar(args),
+            Command::Search(args) => query::search(args),
+            Command::Check(args) => check::check(args),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn listen_should_parse_addresses() -> anyhow::Result<()> {
+        assert_eq!(
+            Listen::Tcp("127.0.0.1:9257".to_string()),
+            "tcp:127.0.0.1:9257".parse()?
+        );
+        assert_eq!(
+            Listen::Unix(PathBuf::from("/tmp/lsp-md.sock")),
+            "unix:/tmp/lsp-md.sock".parse()?
+        );
+        assert!("127.0.0.1:9257".parse::<Listen>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn listen_should_conflict_with_commands() {
+        assert!(
+            Cli::try_parse_from(["lsp-md", "--listen", "tcp:[::1]:9257"])
+                .is_ok()
+        );
+        assert!(Cli::try_parse_from([
+            "lsp-md",
+            "--listen",
+            "tcp:[::1]:9257",
+            "fmt"
+        ])
+        .is_err());
+    }
+}
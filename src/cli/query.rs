@@ -0,0 +1,255 @@
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+use clap::Args;
+use tower_lsp::lsp_types::Url;
+
+use crate::document::{
+    find_by_keyword, find_similar, heading_location, BertModel, ScoredLocation,
+    SearchOptions, SectionIndex, Workspace,
+};
+use crate::settings::{read_config_file, Settings, SettingsLayers};
+
+#[derive(Debug, Args)]
+pub struct IndexArgs {
+    /// Workspace root.
+    #[arg(default_value = ".")]
+    root: PathBuf,
+}
+
+#[derive(Debug, Args)]
+pub struct SimilarArgs {
+    /// Markdown file, optionally with the heading anchor of a section, e.g.
+    /// `notes/rust.md#cargo-tips`.
+    section: String,
+    #[command(flatten)]
+    options: QueryArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct SearchArgs {
+    /// Text to search for.
+    query: String,
+    #[command(flatten)]
+    options: QueryArgs,
+}
+
+#[derive(Debug, Args)]
+struct QueryArgs {
+    /// Workspace root.
+    #[arg(long, default_value = ".")]
+    root: PathBuf,
+    /// Number of results, `similarLimit` of `.lsp-md.toml` by default.
+    #[arg(long)]
+    limit: Option<usize>,
+    /// Leave out results scoring below this.
+    #[arg(long)]
+    min_score: Option<f32>,
+    /// Print results as a JSON array of scored locations.
+    #[arg(long)]
+    json: bool,
+}
+
+/// Markdown files under a root with their section index, restored from and
+/// saved to the cache the language server uses.
+struct Session {
+    settings: Settings,
+    workspace: Workspace,
+    index: SectionIndex,
+    model: BertModel,
+    cache: Option<PathBuf>,
+}
+
+impl Session {
+    fn open(root: &Path) -> anyhow::Result<Self> {
+        let root = root.canonicalize()?;
+        let settings = SettingsLayers {
+            file: read_config_file(&[&root])?,
+            ..Default::default()
+        }
+        .settings()?;
+
+        let workspace = Workspace::default();
+        workspace.set_roots(vec![root]);
+        workspace.set_headings(settings.headings()?);
+        let (files, errors) = workspace.files();
+        for (path, err) in errors {
+            eprintln!("skipped {}: {}", path.display(), err);
+        }
+        for path in files {
+            if let Err(err) = workspace.index_file(&path) {
+                eprintln!("failed to index {}: {:?}", path.display(), err);
+            }
+        }
+
+        let index = SectionIndex::default();
+        let cache =
+            SectionIndex::cache_path(&workspace.roots(), settings.model);
+        if let Some(path) = cache.as_ref().filter(|it| it.exists()) {
+            if let Err(err) = index.load(path) {
+                eprintln!("ignoring index cache {}: {:?}", path.display(), err);
+            }
+        }
+        let model = BertModel::new(settings.model)?;
+
+        Ok(Session {
+            settings,
+            workspace,
+            index,
+            model,
+            cache,
+        })
+    }
+
+    /// Embed sections that changed since the cache was written. Returns the
+    /// number of sections.
+    fn embed(&self) -> anyhow::Result<usize> {
+        let mut sections = 0;
+        for uri in self.workspace.uris() {
+            let Some(doc) = self.workspace.get(&uri) else {
+                continue;
+            };
+            sections += self.index.embed(&uri, doc.value(), &self.model)?.len();
+        }
+        Ok(sections)
+    }
+
+    fn save(&self) -> anyhow::Result<()> {
+        if let Some(path) = &self.cache {
+            self.index.save(path, |it| self.workspace.in_roots(it))?;
+        }
+        Ok(())
+    }
+
+    fn options(&self, args: &QueryArgs) -> SearchOptions {
+        SearchOptions {
+            limit: Some(args.limit.unwrap_or(self.settings.similar_limit)),
+            min_score: args.min_score,
+            ..Default::default()
+        }
+    }
+}
+
+pub fn index(args: IndexArgs) -> anyhow::Result<ExitCode> {
+    let session = Session::open(&args.root)?;
+    let sections = session.embed()?;
+    session.save()?;
+    eprintln!(
+        "indexed {} files, {} sections",
+        session.workspace.uris().len(),
+        sections
+    );
+    Ok(ExitCode::SUCCESS)
+}
+
+pub fn similar(args: SimilarArgs) -> anyhow::Result<ExitCode> {
+    let (file, anchor) = match args.section.rsplit_once('#') {
+        Some((file, anchor)) => (file, Some(anchor)),
+        None => (args.section.as_str(), None),
+    };
+    let path = Path::new(file).canonicalize()?;
+    let uri = Url::from_file_path(&path)
+        .map_err(|_| anyhow::anyhow!("invalid path: {}", file))?;
+
+    let session = Session::open(&args.options.root)?;
+    if session.workspace.get(&uri).is_none() {
+        session.workspace.index_file(&path)?;
+    }
+    session.embed()?;
+    let loc = {
+        let doc = session.workspace.get(&uri).unwrap();
+        heading_location(&uri, doc.value(), anchor).ok_or_else(|| {
+            anyhow::anyhow!("no heading {} in {}", anchor.unwrap_or(""), file)
+        })?
+    };
+    let results = find_similar(
+        &session.workspace,
+        &session.index,
+        &session.model,
+        &loc,
+        &session.options(&args.options),
+    )?;
+    session.save()?;
+    print_results(&results, args.options.json)
+}
+
+pub fn search(args: SearchArgs) -> anyhow::Result<ExitCode> {
+    let session = Session::open(&args.options.root)?;
+    session.embed()?;
+    let origin = Url::from_directory_path(&session.workspace.roots()[0])
+        .map_err(|_| anyhow::anyhow!("invalid root"))?;
+    let results = find_by_keyword(
+        &session.workspace,
+        &session.index,
+        &session.model,
+        &origin,
+        &args.query,
+        &session.options(&args.options),
+    )?;
+    session.save()?;
+    print_results(&results, args.options.json)
+}
+
+/// One `path:line:column<TAB>score<TAB>title` line per result, or JSON.
+fn print_results(
+    results: &[ScoredLocation<'_>],
+    json: bool,
+) -> anyhow::Result<ExitCode> {
+    if json {
+        println!("{}", serde_json::to_string_pretty(results)?);
+    } else {
+        for it in results {
+            println!("{}", result_line(it));
+        }
+    }
+    Ok(ExitCode::SUCCESS)
+}
+
+fn result_line(it: &ScoredLocation<'_>) -> String {
+    let path = it
+        .location
+        .uri
+        .to_file_path()
+        .map(|it| {
+            let cwd = std::env::current_dir().unwrap_or_default();
+            it.strip_prefix(&cwd).map(Path::to_path_buf).unwrap_or(it)
+        })
+        .map(|it| it.display().to_string())
+        .unwrap_or_else(|_| it.location.uri.to_string());
+    let start = it.location.range.start;
+    format!(
+        "{}:{}:{}\t{:.3}\t{}",
+        path,
+        start.line + 1,
+        start.character + 1,
+        it.score,
+        it.title.trim()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use tower_lsp::lsp_types::{Location, Position, Range};
+
+    use super::*;
+
+    #[test]
+    fn result_line_should_be_grep_style() -> anyhow::Result<()> {
+        let pos = Position::new(4, 2);
+        let it = ScoredLocation {
+            score: 0.8125,
+            title: Cow::Borrowed("## Setup"),
+            location: Location::new(
+                Url::parse("file:///notes/a.md")?,
+                Range::new(pos, pos),
+            ),
+            vector_score: None,
+            lexical_score: None,
+            chunk: None,
+        };
+        assert_eq!("/notes/a.md:5:3\t0.812\t## Setup", result_line(&it));
+        Ok(())
+    }
+}
@@ -2,7 +2,7 @@ use std::sync::Mutex;
 
 use serde::Serialize;
 
-#[derive(Serialize, Debug)]
+#[derive(Serialize, Debug, Clone)]
 pub struct Keyword {
     pub score: f32,
     pub text: String,
@@ -22,11 +22,6 @@ pub trait Keywords {
         &self,
         texts: &[&str],
     ) -> anyhow::Result<Vec<Vec<Keyword>>>;
-
-    fn extract(&self, text: &str) -> anyhow::Result<Vec<Keyword>> {
-        self.extract_batch(&[text])
-            .map(|v| v.into_iter().next().unwrap())
-    }
 }
 
 impl<T> Keywords for Mutex<T>
@@ -82,7 +77,7 @@ actions. Not sure how to make it follow-up the document changes properly."#;
     #[test]
     fn test_using_module() -> anyhow::Result<()> {
         let models = super::super::model::BertModel::default();
-        let _ = models.extract(TEST_SECTION);
+        let _ = models.extract_batch(&[TEST_SECTION]);
         Ok(())
     }
 }
@@ -2,7 +2,10 @@ mod embedding;
 mod encoder;
 mod keywords;
 mod model;
+mod quantized;
 
+pub use embedding::{Embedding, DIMENSIONS};
 pub use encoder::Encoder;
 pub use keywords::{Keyword, Keywords};
-pub use model::BertModel;
+pub use model::{BertModel, ModelType};
+pub use quantized::QuantizedEmbedding;
@@ -4,25 +4,53 @@ use rust_bert::pipelines::keywords_extraction::{
 use rust_bert::pipelines::sentence_embeddings::{
     SentenceEmbeddingsConfig, SentenceEmbeddingsModelType,
 };
+use serde::{Deserialize, Serialize};
 
-use super::embedding::Embedding;
+use super::embedding::{Embedding, DIMENSIONS};
 use super::keywords::{Keyword, Keywords};
 use super::Encoder;
 
+/// Sentence models producing the 384 dimensional embeddings the index
+/// stores.
+#[derive(
+    Debug, Default, Clone, Copy, PartialEq, Eq, Hash, Deserialize, Serialize,
+)]
+pub enum ModelType {
+    #[default]
+    #[serde(rename = "all-MiniLM-L12-v2")]
+    AllMiniLmL12V2,
+    #[serde(rename = "all-MiniLM-L6-v2")]
+    AllMiniLmL6V2,
+}
+
+impl From<ModelType> for SentenceEmbeddingsModelType {
+    fn from(value: ModelType) -> Self {
+        match value {
+            ModelType::AllMiniLmL12V2 => Self::AllMiniLmL12V2,
+            ModelType::AllMiniLmL6V2 => Self::AllMiniLmL6V2,
+        }
+    }
+}
+
 pub struct BertModel {
     model: KeywordExtractionModel<'static>,
 }
 
-impl Default for BertModel {
-    fn default() -> Self {
+impl BertModel {
+    pub fn new(model_type: ModelType) -> anyhow::Result<Self> {
         let model = KeywordExtractionModel::new(KeywordExtractionConfig {
             sentence_embeddings_config: SentenceEmbeddingsConfig::from(
-                SentenceEmbeddingsModelType::AllMiniLmL12V2,
+                SentenceEmbeddingsModelType::from(model_type),
             ),
             ..KeywordExtractionConfig::default()
-        })
-        .expect("Failed to load model");
-        Self { model }
+        })?;
+        Ok(Self { model })
+    }
+}
+
+impl Default for BertModel {
+    fn default() -> Self {
+        Self::new(ModelType::default()).expect("Failed to load model")
     }
 }
 
@@ -32,13 +60,17 @@ impl Encoder for BertModel {
         S: AsRef<str> + Sync,
     {
         let v1 = self.model.sentence_embeddings_model.encode(sentences)?;
-        let v2 =
-            v1.into_iter().map(|v| {
-                Embedding::new(v.try_into().unwrap_or_else(|_| {
-                    panic!("Expected array with 384 elements")
-                }))
-            });
-        Ok(v2.collect())
+        v1.into_iter()
+            .map(|v| {
+                anyhow::ensure!(
+                    v.len() == DIMENSIONS,
+                    "expected {} dimensional embeddings, the model produced {}",
+                    DIMENSIONS,
+                    v.len()
+                );
+                Ok(Embedding::new(v))
+            })
+            .collect()
     }
 }
 
@@ -0,0 +1,186 @@
+use serde::de::{self, SeqAccess, Visitor};
+use serde::ser::SerializeTuple;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use super::{Embedding, DIMENSIONS};
+
+/// Embedding quantized to int8 with a single scale, about a quarter of the
+/// size of the float form. Dot products are exact integer sums, so ranking
+/// by them only differs from float ranking on near ties.
+#[derive(Debug, PartialEq, Clone)]
+pub struct QuantizedEmbedding {
+    scale: f32,
+    values: [i8; DIMENSIONS],
+}
+
+impl QuantizedEmbedding {
+    pub fn new(embedding: &Embedding) -> Self {
+        let v = embedding.values();
+        let max = v.iter().fold(0.0f32, |acc, it| acc.max(it.abs()));
+        if max == 0.0 {
+            return QuantizedEmbedding {
+                scale: 0.0,
+                values: [0; DIMENSIONS],
+            };
+        }
+
+        let mut values = [0; DIMENSIONS];
+        for i in 0..DIMENSIONS {
+            values[i] = (v[i] / max * 127.0).round() as i8;
+        }
+        QuantizedEmbedding {
+            scale: max / 127.0,
+            values,
+        }
+    }
+
+    // Approximate dot product of the original embeddings. Accumulates in i32
+    // so the compiler can vectorize the loop.
+    pub fn dot(&self, other: &Self) -> f32 {
+        let mut sum: i32 = 0;
+        for i in 0..DIMENSIONS {
+            sum += self.values[i] as i32 * other.values[i] as i32;
+        }
+        sum as f32 * self.scale * other.scale
+    }
+}
+
+// Serialized as (scale, bytes) so CBOR stores the values as one byte string.
+impl Serialize for QuantizedEmbedding {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let bytes = self.values.map(|it| it as u8);
+        let mut tuple = serializer.serialize_tuple(2)?;
+        tuple.serialize_element(&self.scale)?;
+        tuple.serialize_element(&Bytes(&bytes))?;
+        tuple.end()
+    }
+}
+
+struct Bytes<'a>(&'a [u8]);
+
+impl Serialize for Bytes<'_> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_bytes(self.0)
+    }
+}
+
+struct Values([i8; DIMENSIONS]);
+
+impl<'de> Deserialize<'de> for Values {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct ValuesVisitor;
+
+        impl<'de> Visitor<'de> for ValuesVisitor {
+            type Value = Values;
+
+            fn expecting(
+                &self,
+                formatter: &mut std::fmt::Formatter,
+            ) -> std::fmt::Result {
+                write!(formatter, "{} bytes", DIMENSIONS)
+            }
+
+            fn visit_bytes<E>(self, v: &[u8]) -> Result<Self::Value, E>
+            where
+                E: de::Error,
+            {
+                if v.len() != DIMENSIONS {
+                    return Err(E::invalid_length(v.len(), &self));
+                }
+                let mut values = [0; DIMENSIONS];
+                for i in 0..DIMENSIONS {
+                    values[i] = v[i] as i8;
+                }
+                Ok(Values(values))
+            }
+        }
+
+        deserializer.deserialize_bytes(ValuesVisitor)
+    }
+}
+
+impl<'de> Deserialize<'de> for QuantizedEmbedding {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        struct QuantizedVisitor;
+
+        impl<'de> Visitor<'de> for QuantizedVisitor {
+            type Value = QuantizedEmbedding;
+
+            fn expecting(
+                &self,
+                formatter: &mut std::fmt::Formatter,
+            ) -> std::fmt::Result {
+                write!(formatter, "a scale and {} bytes", DIMENSIONS)
+            }
+
+            fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+            where
+                A: SeqAccess<'de>,
+            {
+                let scale = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(0, &self))?;
+                let Values(values) = seq
+                    .next_element()?
+                    .ok_or_else(|| de::Error::invalid_length(1, &self))?;
+                Ok(QuantizedEmbedding { scale, values })
+            }
+        }
+
+        deserializer.deserialize_tuple(2, QuantizedVisitor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn embedding(seed: usize) -> Embedding {
+        Embedding::new(
+            (0..384)
+                .map(|i| ((i * 7 + seed * 13) % 23) as f32 - 11.0)
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn test_encode() -> anyhow::Result<()> {
+        let value = QuantizedEmbedding::new(&embedding(1));
+        let mut buf = Vec::<u8>::new();
+        ciborium::into_writer(&value, &mut buf)?;
+        // A float embedding takes 1923 bytes.
+        assert_eq!(buf.len(), 393);
+        let decoded: QuantizedEmbedding =
+            ciborium::from_reader(buf.as_slice())?;
+        assert_eq!(value, decoded);
+
+        Ok(())
+    }
+
+    #[test]
+    fn dot_should_approximate_cos() {
+        let (a, b) = (embedding(1), embedding(2));
+        let (qa, qb) =
+            (QuantizedEmbedding::new(&a), QuantizedEmbedding::new(&b));
+        assert!((a.cos(&b) - qa.dot(&qb)).abs() < 0.01);
+        assert!((1.0 - qa.dot(&qa)).abs() < 0.01);
+    }
+
+    #[test]
+    fn zero_should_stay_zero() {
+        let zero = QuantizedEmbedding::new(&Embedding::new(vec![0.0; 384]));
+        assert_eq!(0.0, zero.dot(&QuantizedEmbedding::new(&embedding(1))));
+    }
+}
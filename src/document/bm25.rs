@@ -0,0 +1,129 @@
+use std::collections::HashMap;
+
+const K1: f32 = 1.2;
+const B: f32 = 0.75;
+
+/// A section of a document, identified by document uri and section index.
+pub type SectionKey = (String, usize);
+
+/// Inverted index over section text, scored with BM25.
+#[derive(Default)]
+pub struct Bm25 {
+    postings: HashMap<String, HashMap<SectionKey, u32>>,
+    lengths: HashMap<SectionKey, usize>,
+    terms: HashMap<String, Vec<String>>,
+    total_len: usize,
+}
+
+/// Lowercased runs of letters, digits and underscores, so identifiers and
+/// error codes such as `E0433` survive as single terms.
+pub fn tokenize(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !(c.is_alphanumeric() || c == '_'))
+        .filter(|it| !it.is_empty())
+        .map(|it| it.to_lowercase())
+}
+
+impl Bm25 {
+    /// Replace every section of the document.
+    pub fn insert<S: AsRef<str>>(&mut self, uri: &str, sections: &[S]) {
+        self.remove(uri);
+
+        let mut doc_terms = Vec::new();
+        for (i, text) in sections.iter().enumerate() {
+            let key = (uri.to_string(), i);
+            let mut len = 0;
+            for term in tokenize(text.as_ref()) {
+                len += 1;
+                let tf = self
+                    .postings
+                    .entry(term.clone())
+                    .or_default()
+                    .entry(key.clone())
+                    .or_default();
+                if *tf == 0 {
+                    doc_terms.push(term);
+                }
+                *tf += 1;
+            }
+            self.lengths.insert(key, len);
+            self.total_len += len;
+        }
+        self.terms.insert(uri.to_string(), doc_terms);
+    }
+
+    pub fn contains(&self, uri: &str) -> bool {
+        self.terms.contains_key(uri)
+    }
+
+    pub fn remove(&mut self, uri: &str) {
+        for term in self.terms.remove(uri).unwrap_or_default() {
+            if let Some(postings) = self.postings.get_mut(&term) {
+                postings.retain(|(it, _), _| it != uri);
+                if postings.is_empty() {
+                    self.postings.remove(&term);
+                }
+            }
+        }
+        self.lengths.retain(|(it, _), len| {
+            if it == uri {
+                self.total_len -= *len;
+            }
+            it != uri
+        });
+    }
+
+    /// BM25 score of every section containing at least one query term.
+    pub fn search(&self, query: &str) -> HashMap<SectionKey, f32> {
+        let mut ret: HashMap<SectionKey, f32> = HashMap::new();
+        if self.lengths.is_empty() {
+            return ret;
+        }
+        let n = self.lengths.len() as f32;
+        let avg_len = self.total_len as f32 / n;
+
+        for term in tokenize(query) {
+            let Some(postings) = self.postings.get(&term) else {
+                continue;
+            };
+            let df = postings.len() as f32;
+            let idf = ((n - df + 0.5) / (df + 0.5) + 1.0).ln();
+            for (key, &tf) in postings {
+                let tf = tf as f32;
+                let len = self.lengths[key] as f32;
+                let norm = K1 * (1.0 - B + B * len / avg_len.max(1.0));
+                *ret.entry(key.clone()).or_default() +=
+                    idf * tf * (K1 + 1.0) / (tf + norm);
+            }
+        }
+        ret
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokenize_should_keep_identifiers() {
+        assert_eq!(
+            vec!["error", "e0433", "in", "my_module"],
+            tokenize("Error E0433 in `my_module`!").collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn search_should_rank_exact_terms() {
+        let mut index = Bm25::default();
+        index.insert("a", &["fix error E0433 in parser", "unrelated text"]);
+        index.insert("b", &["another error without code"]);
+
+        let res = index.search("E0433 error");
+        assert!(res[&("a".to_string(), 0)] > res[&("b".to_string(), 0)]);
+        assert!(!res.contains_key(&("a".to_string(), 1)));
+
+        index.remove("a");
+        let res = index.search("E0433 error");
+        assert_eq!(1, res.len());
+        assert_eq!(4, index.total_len);
+    }
+}
@@ -0,0 +1,115 @@
+use std::ops::Range;
+
+use tree_sitter::Node;
+
+use super::document::{SliceAccess, SyntaxTree};
+
+/// Words per chunk. MiniLM reads at most 128 word pieces and prose averages a
+/// little over one piece per word.
+const CHUNK_WORDS: usize = 96;
+
+/// Split the byte range of a section into chunks that fit the encoder, cut at
+/// block boundaries (paragraphs, list items, code blocks, headings).
+/// Consecutive chunks share a block when it fits, so context carries over.
+/// Headings stay with the block after them, and a single block longer than
+/// the limit becomes a chunk of its own.
+pub fn chunks<D>(doc: &D, range: Range<usize>) -> Vec<Range<usize>>
+where
+    D: SyntaxTree + SliceAccess,
+{
+    let mut blocks = Vec::new();
+    collect(doc.tree().root_node(), &range, &mut blocks);
+    if blocks.is_empty() {
+        return vec![range];
+    }
+    let words: Vec<usize> = blocks
+        .iter()
+        .map(|it| doc.slice(it.1.clone()).split_whitespace().count())
+        .collect();
+    let heading: Vec<bool> = blocks.iter().map(|it| it.0).collect();
+    let blocks: Vec<Range<usize>> = blocks.into_iter().map(|it| it.1).collect();
+
+    let mut ret = Vec::new();
+    let mut start = 0;
+    let mut count = 0;
+    for i in 0..blocks.len() {
+        if i > start && !heading[i - 1] && count + words[i] > CHUNK_WORDS {
+            ret.push(blocks[start].start..blocks[i - 1].end);
+            start = if i - 1 > start && words[i - 1] + words[i] <= CHUNK_WORDS {
+                i - 1
+            } else {
+                i
+            };
+            count = words[start..i].iter().sum();
+        }
+        count += words[i];
+    }
+    ret.push(blocks[start].start..blocks[blocks.len() - 1].end);
+    ret
+}
+
+/// Blocks overlapping the range, flagged if they are headings.
+fn collect(
+    node: Node<'_>,
+    range: &Range<usize>,
+    ret: &mut Vec<(bool, Range<usize>)>,
+) {
+    let r = node.byte_range();
+    if r.end <= range.start || r.start >= range.end {
+        return;
+    }
+    match node.kind() {
+        "document" | "section" => {
+            let mut cursor = node.walk();
+            for child in node.named_children(&mut cursor) {
+                collect(child, range, ret);
+            }
+        },
+        "list" => {
+            let mut cursor = node.walk();
+            for item in node.named_children(&mut cursor) {
+                if item.kind() == "list_item" {
+                    ret.push((false, clamp(item.byte_range(), range)));
+                }
+            }
+        },
+        kind => ret.push((kind.ends_with("_heading"), clamp(r, range))),
+    }
+}
+
+fn clamp(r: Range<usize>, range: &Range<usize>) -> Range<usize> {
+    r.start.max(range.start)..r.end.min(range.end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::Document;
+
+    #[test]
+    fn chunks_should_split_at_blocks() -> anyhow::Result<()> {
+        let para = "word ".repeat(40);
+        let text = format!(
+            "# Long\n\n{para}\n\n{para}\n\n- {para}\n- short item\n\n# Next\n\nx\n"
+        );
+        let doc = Document::parse(&text)?;
+        let section = 0..text.find("# Next").unwrap();
+
+        let res = chunks(&doc, section.clone());
+        let texts: Vec<String> = res
+            .iter()
+            .map(|it| doc.slice(it.clone()).into_owned())
+            .collect();
+        assert_eq!(2, res.len());
+        assert!(texts[0].starts_with("# Long"));
+        // The second paragraph is shared by the first two chunks.
+        assert!(res[1].start < res[0].end);
+        assert!(texts[1].trim_end().ends_with("- short item"));
+        assert!(res.iter().all(|it| it.end <= section.end));
+
+        let short = text.find("# Next").unwrap()..text.len();
+        assert_eq!(vec![short.clone()], chunks(&doc, short));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,262 @@
+use std::borrow::Cow;
+use std::cmp::Reverse;
+
+use serde::{Deserialize, Serialize};
+use tower_lsp::lsp_types::Location;
+
+use super::bert::{Embedding, Keywords, DIMENSIONS};
+use super::document::DocumentExt;
+use super::document_adapter::DocumentLsp;
+use super::section_index::SectionIndex;
+use super::workspace::Workspace;
+use super::{Encoder, ScoredLocation};
+
+const MAX_ITERATIONS: usize = 50;
+/// Keywords kept as the label of a cluster.
+const LABEL_KEYWORDS: usize = 3;
+/// Member text passed to keyword extraction per cluster; the encoder reads
+/// only the start of it anyway.
+const LABEL_TEXT_LEN: usize = 4000;
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ClusterOptions {
+    /// Number of clusters, about `sqrt(sections / 2)` unless given.
+    pub k: Option<usize>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Cluster {
+    pub label: String,
+    pub keywords: Vec<String>,
+    /// Members by descending similarity to the cluster centroid.
+    pub members: Vec<ScoredLocation<'static>>,
+}
+
+struct Member {
+    location: ScoredLocation<'static>,
+    text: String,
+    embedding: Embedding,
+}
+
+/// Group every section in the workspace by k-means over section embeddings
+/// and label each group with keywords of its members. Largest clusters come
+/// first.
+pub fn clusters(
+    workspace: &Workspace,
+    index: &SectionIndex,
+    enc: &(impl Encoder + Keywords),
+    opts: &ClusterOptions,
+) -> anyhow::Result<Vec<Cluster>> {
+    let members = members(workspace, index, enc)?;
+    if members.is_empty() {
+        return Ok(Vec::new());
+    }
+    let k = opts
+        .k
+        .unwrap_or(((members.len() as f32 / 2.0).sqrt().round()) as usize)
+        .clamp(1, members.len());
+
+    let embeddings: Vec<&Embedding> =
+        members.iter().map(|it| &it.embedding).collect();
+    let (centroids, assignment) = kmeans(&embeddings, k);
+
+    let mut groups: Vec<Vec<Member>> = (0..k).map(|_| Vec::new()).collect();
+    for (mut member, cluster) in members.into_iter().zip(assignment) {
+        member.location.score = member.embedding.cos(&centroids[cluster]);
+        groups[cluster].push(member);
+    }
+    groups.retain(|it| !it.is_empty());
+    groups.sort_by_key(|it| Reverse(it.len()));
+    for group in groups.iter_mut() {
+        group.sort_by(|a, b| b.location.score.total_cmp(&a.location.score));
+    }
+
+    let texts: Vec<String> = groups
+        .iter()
+        .map(|group| {
+            let mut text = String::new();
+            for it in group {
+                if text.len() >= LABEL_TEXT_LEN {
+                    break;
+                }
+                text.push_str(&it.text);
+                text.push('\n');
+            }
+            text
+        })
+        .collect();
+    let texts: Vec<&str> = texts.iter().map(|it| it.as_str()).collect();
+    let keywords = enc.extract_batch(&texts)?;
+
+    Ok(groups
+        .into_iter()
+        .zip(keywords)
+        .map(|(group, keywords)| {
+            let keywords: Vec<String> = keywords
+                .into_iter()
+                .take(LABEL_KEYWORDS)
+                .map(|it| it.text)
+                .collect();
+            Cluster {
+                label: keywords.join(", "),
+                keywords,
+                members: group.into_iter().map(|it| it.location).collect(),
+            }
+        })
+        .collect())
+}
+
+fn members(
+    workspace: &Workspace,
+    index: &SectionIndex,
+    enc: &impl Encoder,
+) -> anyhow::Result<Vec<Member>> {
+    let mut ret = Vec::new();
+    for uri in workspace.uris() {
+        let Some(doc) = workspace.get(&uri) else {
+            continue;
+        };
+        let doc = doc.value();
+        for (i, embedding) in
+            index.embed(&uri, doc, enc)?.into_iter().enumerate()
+        {
+            let Some(range) = doc.section_to_title_range(i) else {
+                continue;
+            };
+            ret.push(Member {
+                location: ScoredLocation {
+                    score: 0.0,
+                    title: Cow::Owned(DocumentExt::title(doc, i)?.into_owned()),
+                    location: Location::new(uri.clone(), range),
+                    vector_score: None,
+                    lexical_score: None,
+                    chunk: None,
+                },
+                text: DocumentExt::text(doc, i)?.into_owned(),
+                embedding,
+            });
+        }
+    }
+    Ok(ret)
+}
+
+/// Spherical k-means with farthest-first seeding, so results are
+/// deterministic. Returns the centroids and the cluster of each point.
+fn kmeans(points: &[&Embedding], k: usize) -> (Vec<Embedding>, Vec<usize>) {
+    let mut centroids = vec![mean(points.iter().copied())];
+    centroids[0] = (*nearest(points, &centroids[0])).clone();
+    while centroids.len() < k {
+        let farthest = points
+            .iter()
+            .min_by(|a, b| {
+                closest(&centroids, a)
+                    .1
+                    .total_cmp(&closest(&centroids, b).1)
+            })
+            .unwrap();
+        centroids.push((*farthest).clone());
+    }
+
+    let mut assignment = vec![usize::MAX; points.len()];
+    for _ in 0..MAX_ITERATIONS {
+        let next: Vec<usize> =
+            points.iter().map(|it| closest(&centroids, it).0).collect();
+        if next == assignment {
+            break;
+        }
+        assignment = next;
+        for (i, centroid) in centroids.iter_mut().enumerate() {
+            let members = points
+                .iter()
+                .zip(&assignment)
+                .filter(|(_, &c)| c == i)
+                .map(|(it, _)| *it);
+            if assignment.contains(&i) {
+                *centroid = mean(members);
+            }
+        }
+    }
+    (centroids, assignment)
+}
+
+/// Index of and similarity to the most similar centroid.
+fn closest(centroids: &[Embedding], point: &Embedding) -> (usize, f32) {
+    centroids
+        .iter()
+        .map(|it| it.cos(point))
+        .enumerate()
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .unwrap_or((0, 0.0))
+}
+
+fn nearest<'a>(points: &[&'a Embedding], target: &Embedding) -> &'a Embedding {
+    points
+        .iter()
+        .max_by(|a, b| a.cos(target).total_cmp(&b.cos(target)))
+        .unwrap()
+}
+
+/// Normalized mean direction.
+fn mean<'a>(points: impl Iterator<Item = &'a Embedding>) -> Embedding {
+    let mut sum = vec![0.0; DIMENSIONS];
+    for point in points {
+        for (acc, it) in sum.iter_mut().zip(point.values()) {
+            *acc += it;
+        }
+    }
+    Embedding::new(sum)
+}
+
+#[cfg(test)]
+mod tests {
+    use tower_lsp::lsp_types::Url;
+
+    use super::*;
+    use crate::document::{BertModel, Document};
+
+    #[test]
+    fn clusters_should_group_topics() -> anyhow::Result<()> {
+        let workspace = Workspace::default();
+        workspace.insert(
+            &Url::parse("file:///notes/a.md")?,
+            Document::parse(
+                "# Pasta\n\nCook pasta in salted water.\n\n# Rust\n\nThe borrow \
+                 checker in rust.\n",
+            )?,
+        );
+        workspace.insert(
+            &Url::parse("file:///notes/b.md")?,
+            Document::parse(
+                "# Sauce\n\nCook tomato sauce for pasta.\n\n# Lifetimes\n\nRust \
+                 lifetimes and the borrow checker.\n",
+            )?,
+        );
+        let model = BertModel::default();
+
+        let res = clusters(
+            &workspace,
+            &SectionIndex::default(),
+            &model,
+            &ClusterOptions { k: Some(2) },
+        )?;
+        assert_eq!(2, res.len());
+        let mut titles: Vec<Vec<&str>> = res
+            .iter()
+            .map(|it| {
+                let mut titles: Vec<&str> =
+                    it.members.iter().map(|it| it.title.as_ref()).collect();
+                titles.sort();
+                titles
+            })
+            .collect();
+        titles.sort();
+        assert_eq!(
+            vec![vec!["Lifetimes", "Rust"], vec!["Pasta", "Sauce"]],
+            titles
+        );
+        assert!(res.iter().all(|it| !it.label.is_empty()));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,171 @@
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tower_lsp::lsp_types::{CodeLens, Command, Location, Url};
+
+use super::bert::Keywords;
+use super::document_adapter::DocumentLsp;
+use super::keyword_index::KeywordIndex;
+use super::links::section_backlinks;
+use super::section_index::{SearchOptions, SectionIndex};
+use super::similar_notes::{find_similar, query_section_titles};
+use super::workspace::Workspace;
+use super::{Document, Encoder};
+
+/// Similar notes counted by the similar lens; more show as `10+`.
+const SIMILAR_LENS_LIMIT: usize = 10;
+const SIMILAR_LENS_MIN_SCORE: f32 = 0.7;
+/// Keywords shown in the title of the keyword lens.
+const KEYWORD_LENS_COUNT: usize = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+enum LensKind {
+    Similar,
+    Keywords,
+    Backlinks,
+}
+
+/// What a lens resolves to, kept in its `data`.
+#[derive(Debug, Deserialize, Serialize)]
+struct LensData {
+    uri: Url,
+    kind: LensKind,
+}
+
+/// Unresolved lenses for every section heading: ranges only, titles and
+/// commands are filled in by `resolve_code_lens`.
+pub fn code_lenses(uri: &Url, doc: &Document) -> Vec<CodeLens> {
+    query_section_titles(doc)
+        .into_iter()
+        .flat_map(|range| {
+            [LensKind::Similar, LensKind::Keywords, LensKind::Backlinks]
+                .into_iter()
+                .map(move |kind| CodeLens {
+                    range,
+                    command: None,
+                    data: Some(json!(LensData {
+                        uri: uri.clone(),
+                        kind,
+                    })),
+                })
+        })
+        .collect()
+}
+
+/// Compute the title and command of a lens from `code_lenses`.
+pub fn resolve_code_lens(
+    workspace: &Workspace,
+    index: &SectionIndex,
+    keywords: &KeywordIndex,
+    enc: &(impl Encoder + Keywords),
+    mut lens: CodeLens,
+) -> anyhow::Result<CodeLens> {
+    let data: LensData = serde_json::from_value(
+        lens.data
+            .clone()
+            .ok_or_else(|| anyhow::anyhow!("code lens without data"))?,
+    )?;
+    let loc = Location::new(data.uri.clone(), lens.range);
+
+    let command = match data.kind {
+        LensKind::Similar => {
+            let opts = SearchOptions {
+                limit: Some(SIMILAR_LENS_LIMIT),
+                min_score: Some(SIMILAR_LENS_MIN_SCORE),
+                ..Default::default()
+            };
+            let count = find_similar(workspace, index, enc, &loc, &opts)?.len();
+            let count = if count == SIMILAR_LENS_LIMIT {
+                format!("{}+", count)
+            } else {
+                count.to_string()
+            };
+            Command {
+                title: format!(
+                    "Similar: {} notes ≥{}",
+                    count, SIMILAR_LENS_MIN_SCORE
+                ),
+                command: "lsp_md/showSimilar".to_string(),
+                arguments: Some(vec![json!(loc), json!(opts)]),
+            }
+        },
+        LensKind::Keywords => {
+            let doc = workspace
+                .get(&data.uri)
+                .ok_or_else(|| anyhow::anyhow!("unknown document"))?;
+            let section = doc
+                .position_to_section(&lens.range.start)
+                .ok_or_else(|| anyhow::anyhow!("no section at code lens"))?;
+            let top: Vec<String> = keywords
+                .keywords(&data.uri, doc.value(), enc)?
+                .swap_remove(section)
+                .into_iter()
+                .take(KEYWORD_LENS_COUNT)
+                .map(|it| it.text)
+                .collect();
+            Command {
+                title: if top.is_empty() {
+                    "Keywords".to_string()
+                } else {
+                    format!("Keywords: {}", top.join(", "))
+                },
+                command: "lsp_md/keywords".to_string(),
+                arguments: Some(vec![json!(loc)]),
+            }
+        },
+        LensKind::Backlinks => Command {
+            title: format!(
+                "Backlinks: {}",
+                section_backlinks(workspace, &loc)?.len()
+            ),
+            command: "lsp_md/backlinks".to_string(),
+            arguments: Some(vec![json!(loc)]),
+        },
+    };
+    lens.command = Some(command);
+    Ok(lens)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::BertModel;
+
+    #[test]
+    fn code_lenses_should_resolve_lazily() -> anyhow::Result<()> {
+        let workspace = Workspace::default();
+        let a = Url::parse("file:///notes/a.md")?;
+        workspace.insert(
+            &a,
+            Document::parse("# Cargo Tips\n\nCargo builds rust crates.\n")?,
+        );
+        workspace.insert(
+            &Url::parse("file:///notes/b.md")?,
+            Document::parse("# Links\n\nSee [tips](a.md#cargo-tips).\n")?,
+        );
+        let model = BertModel::default();
+
+        let lenses = code_lenses(&a, workspace.get(&a).unwrap().value());
+        assert_eq!(3, lenses.len());
+        assert!(lenses.iter().all(|it| it.command.is_none()));
+
+        let titles: Vec<String> = lenses
+            .into_iter()
+            .map(|it| {
+                resolve_code_lens(
+                    &workspace,
+                    &SectionIndex::default(),
+                    &KeywordIndex::default(),
+                    &model,
+                    it,
+                )
+                .map(|it| it.command.unwrap().title)
+            })
+            .collect::<anyhow::Result<_>>()?;
+        assert!(titles[0].starts_with("Similar: "));
+        assert!(titles[1].starts_with("Keywords: "));
+        assert_eq!("Backlinks: 1", titles[2]);
+
+        Ok(())
+    }
+}
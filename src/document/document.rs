@@ -2,6 +2,8 @@ use std::borrow::Cow;
 use std::ops::{Range, RangeBounds};
 use std::slice::SliceIndex;
 
+use tree_sitter::Tree;
+
 pub trait SliceAccess {
     fn slice<'a, R: RangeBounds<usize> + SliceIndex<str, Output = str>>(
         &'a self,
@@ -9,7 +11,7 @@ pub trait SliceAccess {
     ) -> Cow<'a, str>;
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Section {
     pub(super) title: Range<usize>,
     pub(super) range: Range<usize>,
@@ -21,6 +23,11 @@ pub trait BasicDocument {
     fn sections(&self) -> Self::Output;
 }
 
+/// Access to the tree-sitter syntax tree backing a document.
+pub trait SyntaxTree {
+    fn tree(&self) -> &Tree;
+}
+
 pub trait DocumentExt<'a> {
     type Output: Into<Cow<'a, str>>;
 
@@ -4,15 +4,18 @@ use std::borrow::Cow;
 use std::ops::{Range, RangeBounds};
 use std::slice::SliceIndex;
 
-use regex::RegexBuilder;
+use regex::Regex;
 use ropey::Rope;
 use tower_lsp::lsp_types::Position;
+use tree_sitter::Tree;
 
-use super::document::{BasicDocument, Section, SliceAccess};
+use super::document::{BasicDocument, Section, SliceAccess, SyntaxTree};
 use super::document_adapter::{DocumentLsp, LspAdapter};
 use super::format::FormatterV2;
 
-pub struct Document(FormatterV2, Vec<Section>);
+/// A parsed markdown buffer. Sections follow the markdown headings unless a
+/// heading pattern was given.
+pub struct Document(FormatterV2, Option<Vec<Section>>);
 
 impl SliceAccess for Document {
     fn slice<'a, R: RangeBounds<usize> + SliceIndex<str, Output = str>>(
@@ -37,7 +40,16 @@ impl BasicDocument for Document {
     type Output = Vec<Section>;
 
     fn sections(&self) -> Self::Output {
-        self.0.sections()
+        match &self.1 {
+            Some(it) => it.clone(),
+            None => self.0.sections(),
+        }
+    }
+}
+
+impl SyntaxTree for Document {
+    fn tree(&self) -> &Tree {
+        self.0.tree()
     }
 }
 
@@ -49,31 +61,40 @@ impl Document {
     }
 
     pub fn from_str(text: &str) -> anyhow::Result<Self> {
-        let rope = Rope::from_str(&text);
-        let re = RegexBuilder::new(r"^##? (.*)$").multi_line(true).build()?;
-        let mut sections: Vec<Section> = Vec::new();
-        let mut prev_title: Range<usize> = 0..0;
-        for it in re.find_iter(&text) {
-            if prev_title.end != 0 {
-                sections.push(Section {
-                    title: prev_title.clone(),
-                    range: prev_title.start..it.start(),
-                });
-            }
-            prev_title = it.range();
-        }
-
-        if prev_title.end != 0 {
-            sections.push(Section {
-                title: prev_title.clone(),
-                range: prev_title.start..text.len(),
-            });
-        }
+        Document::with_headings(text, None)
+    }
 
+    /// Parse with sections starting at the lines matching `headings`, titled
+    /// by its first capture group, instead of at markdown headings.
+    pub fn with_headings(
+        text: &str,
+        headings: Option<&Regex>,
+    ) -> anyhow::Result<Self> {
+        let rope = Rope::from_str(text);
+        let sections = headings.map(|re| heading_sections(text, re));
         Ok(Self(FormatterV2::new(rope), sections))
     }
 }
 
+/// Sections from each match of `re` to the next.
+fn heading_sections(text: &str, re: &Regex) -> Vec<Section> {
+    let starts: Vec<(Range<usize>, usize)> = re
+        .captures_iter(text)
+        .filter_map(|it| {
+            let line = it.get(0)?;
+            Some((it.get(1).unwrap_or(line).range(), line.start()))
+        })
+        .collect();
+    starts
+        .iter()
+        .enumerate()
+        .map(|(i, (title, start))| Section {
+            title: title.clone(),
+            range: *start..starts.get(i + 1).map_or(text.len(), |it| it.1),
+        })
+        .collect()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -107,4 +128,24 @@ Content of section 2...
             Document::from_str(BUF).unwrap().sections(),
         );
     }
+
+    #[test]
+    fn heading_pattern_should_split_sections() -> anyhow::Result<()> {
+        let re = regex::RegexBuilder::new(r"^Q: (.*)$")
+            .multi_line(true)
+            .build()?;
+        let text = "Intro\nQ: First?\nYes.\nQ: Second?\nNo.\n";
+        let doc = Document::with_headings(text, Some(&re))?;
+        let sections = doc.sections();
+        assert_eq!(
+            vec!["First?", "Second?"],
+            sections
+                .iter()
+                .map(|it| doc.slice(it.title.clone()))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(6..21, sections[0].range);
+        assert_eq!(21..text.len(), sections[1].range);
+        Ok(())
+    }
 }
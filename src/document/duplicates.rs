@@ -0,0 +1,207 @@
+use tower_lsp::lsp_types::{
+    CodeAction, CodeActionKind, Diagnostic, DiagnosticRelatedInformation,
+    DiagnosticSeverity, Location, Range, TextEdit, Url,
+};
+
+use super::document::{BasicDocument, SliceAccess};
+use super::document_adapter::{DocumentLsp, LspAdapter};
+use super::section_index::{SearchOptions, SectionIndex};
+use super::workspace::Workspace;
+use super::Encoder;
+
+/// Diagnostic source of near-duplicate reports, so code actions can pick
+/// them out.
+const SOURCE: &str = "lsp-md";
+/// Duplicates reported per section.
+const MAX_DUPLICATES: usize = 3;
+
+/// An informational diagnostic on every section of the document whose
+/// embedding is at least `threshold` similar to another section in the
+/// workspace. The other section's location is attached as diagnostic data.
+pub fn duplicate_diagnostics(
+    workspace: &Workspace,
+    index: &SectionIndex,
+    enc: &impl Encoder,
+    uri: &Url,
+    threshold: f32,
+) -> anyhow::Result<Vec<Diagnostic>> {
+    let (embeddings, titles) = {
+        let doc = workspace
+            .get(uri)
+            .ok_or_else(|| anyhow::anyhow!("unknown document: {}", uri))?;
+        let titles: Vec<_> = (0..doc.sections().len())
+            .map(|i| doc.section_to_title_range(i))
+            .collect();
+        (index.embed(uri, doc.value(), enc)?, titles)
+    };
+    let opts = SearchOptions {
+        limit: Some(MAX_DUPLICATES),
+        min_score: Some(threshold),
+        ..Default::default()
+    };
+
+    let mut ret = Vec::new();
+    for (embedding, title) in embeddings.iter().zip(titles) {
+        let Some(range) = title else {
+            continue;
+        };
+        let source = Location::new(uri.clone(), range);
+        for it in
+            index.rank(workspace, enc, uri, embedding, Some(&source), &opts)?
+        {
+            let file = it
+                .location
+                .uri
+                .path_segments()
+                .and_then(|mut it| it.next_back())
+                .unwrap_or_default()
+                .to_string();
+            ret.push(Diagnostic {
+                range,
+                severity: Some(DiagnosticSeverity::INFORMATION),
+                source: Some(SOURCE.to_string()),
+                message: format!(
+                    "very similar to \"{}\" in {} ({:.2})",
+                    it.title, file, it.score
+                ),
+                related_information: Some(vec![DiagnosticRelatedInformation {
+                    location: it.location.clone(),
+                    message: "similar section".to_string(),
+                }]),
+                data: Some(serde_json::to_value(&it.location)?),
+                ..Default::default()
+            });
+        }
+    }
+    Ok(ret)
+}
+
+/// For a near-duplicate diagnostic, a quick fix that appends the body of the
+/// flagged section to the similar one and deletes the flagged section.
+pub fn merge_action(
+    workspace: &Workspace,
+    uri: &Url,
+    diagnostic: &Diagnostic,
+) -> Option<CodeAction> {
+    if diagnostic.source.as_deref() != Some(SOURCE) {
+        return None;
+    }
+    let other: Location =
+        serde_json::from_value(diagnostic.data.clone()?).ok()?;
+
+    let (body, delete) = {
+        let doc = workspace.get(uri)?;
+        let index = doc.position_to_section(&diagnostic.range.start)?;
+        let section = &doc.sections()[index];
+        let text = doc.slice(section.title.end..section.range.end);
+        let body = text.find('\n').map_or("", |it| &text[it + 1..]);
+        let delete = Range::new(
+            doc.offset_to_position(section.range.start)?,
+            doc.offset_to_position(section.range.end)?,
+        );
+        (body.trim().to_string(), delete)
+    };
+    let insert = {
+        let doc = workspace.get(&other.uri)?;
+        let index = doc.position_to_section(&other.range.start)?;
+        let section = &doc.sections()[index];
+        let end = section.range.start +
+            doc.slice(section.range.clone()).trim_end().len();
+        doc.offset_to_position(end)?
+    };
+
+    let changes = vec![
+        (
+            other.uri.clone(),
+            vec![TextEdit::new(
+                Range::new(insert, insert),
+                format!("\n\n{}", body),
+            )],
+        ),
+        (uri.clone(), vec![TextEdit::new(delete, String::new())]),
+    ];
+
+    Some(CodeAction {
+        title: "Merge into similar section".to_string(),
+        kind: Some(CodeActionKind::QUICKFIX),
+        diagnostics: Some(vec![diagnostic.clone()]),
+        edit: Some(workspace.edit(changes)),
+        ..Default::default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use tower_lsp::lsp_types::{
+        DocumentChanges, OneOf, OptionalVersionedTextDocumentIdentifier,
+        Position,
+    };
+
+    use super::*;
+    use crate::document::{BertModel, Document};
+
+    #[test]
+    fn duplicates_should_be_merged() -> anyhow::Result<()> {
+        let workspace = Workspace::default();
+        let a = Url::parse("file:///notes/a.md")?;
+        let b = Url::parse("file:///notes/b.md")?;
+        workspace.update(
+            &a,
+            3,
+            Document::parse(
+                "# Setup\n\nInstall rust with rustup and add clippy.\n\n# \
+                 Other\n\nTea.\n",
+            )?,
+        );
+        workspace.insert(
+            &b,
+            Document::parse(
+                "# Setup notes\n\nInstall rust with rustup and add clippy.\n",
+            )?,
+        );
+        let model = BertModel::default();
+        let index = SectionIndex::default();
+
+        let res = duplicate_diagnostics(&workspace, &index, &model, &a, 0.9)?;
+        assert_eq!(1, res.len());
+        assert_eq!(Position::new(0, 2), res[0].range.start);
+        assert!(res[0].message.contains("\"Setup notes\" in b.md"));
+
+        let action = merge_action(&workspace, &a, &res[0]).unwrap();
+        let Some(DocumentChanges::Edits(changes)) =
+            action.edit.unwrap().document_changes
+        else {
+            panic!("expected versioned document edits");
+        };
+        assert_eq!(
+            OptionalVersionedTextDocumentIdentifier {
+                uri: b,
+                version: None
+            },
+            changes[0].text_document
+        );
+        assert_eq!(
+            vec![OneOf::Left(TextEdit::new(
+                Range::new(Position::new(2, 40), Position::new(2, 40)),
+                "\n\nInstall rust with rustup and add clippy.".to_string(),
+            ))],
+            changes[0].edits
+        );
+        assert_eq!(
+            OptionalVersionedTextDocumentIdentifier {
+                uri: a,
+                version: Some(3)
+            },
+            changes[1].text_document
+        );
+        assert_eq!(
+            vec![OneOf::Left(TextEdit::new(
+                Range::new(Position::new(0, 0), Position::new(4, 0)),
+                String::new(),
+            ))],
+            changes[1].edits
+        );
+
+        Ok(())
+    }
+}
@@ -1,33 +1,29 @@
-use std::borrow::Cow;
-
-use tower_lsp::lsp_types::Position;
+use tower_lsp::lsp_types::{Position, Url};
 
 use super::bert::{Keyword, Keywords};
-use super::document::DocumentExt;
 use super::document_adapter::DocumentLsp;
-
-pub fn extract_keywords<'a, D>(
-    doc: &'a D,
+use super::keyword_index::KeywordIndex;
+use super::Document;
+
+/// Keywords of the section at `pos`, from the keyword index.
+pub fn extract_keywords(
+    index: &KeywordIndex,
+    uri: &Url,
+    doc: &Document,
     enc: &impl Keywords,
     pos: &Position,
-) -> anyhow::Result<Vec<Keyword>>
-where
-    D: DocumentLsp + DocumentExt<'a>,
-{
+) -> anyhow::Result<Vec<Keyword>> {
     let current_section_idx =
         doc.position_to_section(pos).expect("Cannot find section");
-    let text: Cow<'a, str> =
-        DocumentExt::text(doc, current_section_idx)?.into();
-    let keywords = enc.extract(&text)?;
+    let mut keywords = index.keywords(uri, doc, enc)?;
 
-    Ok(keywords)
+    Ok(keywords.swap_remove(current_section_idx))
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::document::bert::BertModel;
-    use crate::document::Document;
 
     const BUF: &'static str = r#"
 # Section 1
@@ -48,10 +44,19 @@ Content of section 2...
         let pos = Position::new(1, 0);
         let enc = BertModel::default();
 
-        let res = extract_keywords(&doc, &enc, &pos)?;
+        let uri = Url::parse("file:///notes/a.md")?;
+        let index = KeywordIndex::default();
+
+        let res = extract_keywords(&index, &uri, &doc, &enc, &pos)?;
 
         dbg!(res);
-        dbg!(extract_keywords(&doc, &enc, &Position::new(7, 0))?);
+        dbg!(extract_keywords(
+            &index,
+            &uri,
+            &doc,
+            &enc,
+            &Position::new(7, 0)
+        )?);
 
         Ok(())
     }
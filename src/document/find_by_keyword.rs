@@ -1,52 +1,19 @@
-use std::borrow::Cow;
+use tower_lsp::lsp_types::Url;
 
-use tower_lsp::lsp_types::{Location, Url};
-
-use super::document::DocumentExt;
-use super::document_adapter::DocumentLsp;
+use super::section_index::{SearchOptions, SectionIndex};
+use super::workspace::Workspace;
 use super::{Encoder, ScoredLocation};
 
-pub fn find_by_keyword<'a, D>(
-    uri: Url,
+pub fn find_by_keyword(
+    workspace: &Workspace,
+    index: &SectionIndex,
     model: &impl Encoder,
-    doc: &'a D,
+    uri: &Url,
     keyword: &str,
-) -> Vec<ScoredLocation<'a>>
-where
-    D: DocumentLsp + DocumentExt<'a>,
-{
-    let word_embedding =
-        model.encode(keyword).expect("should calculate embedding");
-    let mut candidates = doc
-        .sections()
-        .as_ref()
-        .into_iter()
-        .enumerate()
-        .map(|(i, _)| {
-            // FIXME: use batch?
-            let text = DocumentExt::text(doc, i).expect("should have section");
-            let title =
-                DocumentExt::title(doc, i).expect("should have section");
-            let embedding = model
-                .encode(Into::<Cow<'a, str>>::into(text))
-                .expect("should calculate embedding");
-            let dist = embedding.cos(&word_embedding);
-            ScoredLocation {
-                score: dist,
-                title: title.into(),
-                location: Location {
-                    uri: uri.clone(),
-                    range: doc
-                        .section_to_title_range(i)
-                        .expect("Should have section"),
-                },
-            }
-        })
-        .collect::<Vec<_>>();
-
-    candidates.sort_by(|a, b| b.score.partial_cmp(&a.score).unwrap());
-
-    candidates
+    opts: &SearchOptions,
+) -> anyhow::Result<Vec<ScoredLocation<'static>>> {
+    let word_embedding = model.encode(keyword)?;
+    index.rank_hybrid(workspace, model, uri, keyword, &word_embedding, opts)
 }
 
 #[cfg(test)]
@@ -61,11 +28,19 @@ mod tests {
     )?;
         let model = BertModel::default();
         let uri = Url::parse("file:///home/user/document.md").unwrap();
+        let workspace = Workspace::default();
+        workspace.insert(&uri, doc);
         let keyword = "keyword";
-        let results = find_by_keyword(uri, &model, &doc, keyword);
-        assert_eq!(results.len(), 2);
-        assert_eq!(results[0].title, "# Title");
-        assert_eq!(results[1].title, "## Subtitle");
+        let results = find_by_keyword(
+            &workspace,
+            &SectionIndex::default(),
+            &model,
+            &uri,
+            keyword,
+            &SearchOptions::default(),
+        )?;
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].title, "Title");
 
         Ok(())
     }
@@ -0,0 +1,135 @@
+use tower_lsp::lsp_types::{FoldingRange, FoldingRangeKind};
+use tree_sitter::Node;
+
+use super::document::{SliceAccess, SyntaxTree};
+
+/// Compute folding ranges from the syntax tree: heading sections (nested),
+/// fenced code blocks, front matter and multi-line items of top-level lists.
+pub fn folding_ranges<D>(doc: &D) -> Vec<FoldingRange>
+where
+    D: SyntaxTree + SliceAccess,
+{
+    let mut ret = Vec::new();
+    collect(doc, doc.tree().root_node(), false, &mut ret);
+    ret
+}
+
+fn collect<D: SliceAccess>(
+    doc: &D,
+    node: Node<'_>,
+    in_list: bool,
+    ret: &mut Vec<FoldingRange>,
+) {
+    match node.kind() {
+        "section" if has_heading(node) => {
+            ret.extend(fold(doc, node, None));
+        },
+        "fenced_code_block" | "minus_metadata" | "plus_metadata" => {
+            ret.extend(fold(doc, node, Some(FoldingRangeKind::Region)));
+            return;
+        },
+        "list" if !in_list => {
+            let mut cursor = node.walk();
+            for item in node.named_children(&mut cursor) {
+                if item.kind() == "list_item" {
+                    ret.extend(fold(doc, item, None));
+                }
+            }
+        },
+        _ => {},
+    }
+
+    let in_list = in_list || node.kind() == "list";
+    let mut cursor = node.walk();
+    for child in node.named_children(&mut cursor) {
+        collect(doc, child, in_list, ret);
+    }
+}
+
+fn has_heading(section: Node<'_>) -> bool {
+    section.child(0).is_some_and(|it| {
+        it.kind() == "atx_heading" || it.kind() == "setext_heading"
+    })
+}
+
+/// Fold the node from its first line to its last non-blank line.
+fn fold<D: SliceAccess>(
+    doc: &D,
+    node: Node<'_>,
+    kind: Option<FoldingRangeKind>,
+) -> Option<FoldingRange> {
+    let start_line = node.start_position().row as u32;
+    let text = doc.slice(node.byte_range());
+    let end_line = start_line + text.trim_end().matches('\n').count() as u32;
+    if end_line <= start_line {
+        return None;
+    }
+
+    Some(FoldingRange {
+        start_line,
+        start_character: None,
+        end_line,
+        end_character: None,
+        kind,
+        collapsed_text: None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::Document;
+
+    const BUF: &str = r#"---
+title: Note
+---
+
+# Top
+
+Intro
+
+## Sub
+
+- item
+  continued
+- single
+  - nested
+    item
+
+```rust
+fn main() {}
+```
+
+# Second
+
+Text
+"#;
+
+    fn lines(ranges: &[FoldingRange]) -> Vec<(u32, u32)> {
+        ranges.iter().map(|r| (r.start_line, r.end_line)).collect()
+    }
+
+    #[test]
+    fn folding_ranges_should_cover_blocks() -> anyhow::Result<()> {
+        let doc = Document::parse(BUF)?;
+        let ranges = folding_ranges(&doc);
+
+        assert_eq!(
+            vec![
+                (0, 2),
+                (4, 18),
+                (8, 18),
+                (10, 11),
+                (12, 14),
+                (16, 18),
+                (20, 22)
+            ],
+            lines(&ranges)
+        );
+        assert_eq!(Some(FoldingRangeKind::Region), ranges[0].kind);
+        assert_eq!(Some(FoldingRangeKind::Region), ranges[5].kind);
+        assert_eq!(None, ranges[1].kind);
+
+        Ok(())
+    }
+}
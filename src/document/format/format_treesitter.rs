@@ -12,9 +12,13 @@ use super::{
     SliceAccess,
 };
 
+/// Line width paragraphs and lists are wrapped at unless configured.
+pub const DEFAULT_WRAP_WIDTH: usize = 80;
+
 pub struct Formatter<'a, T: LspAdapter + SliceAccess> {
     buf: &'a T,
     tree: Tree,
+    width: usize,
 }
 
 impl<'a, T: LspAdapter + SliceAccess> Formatter<'a, T> {
@@ -29,7 +33,43 @@ impl<'a, T: LspAdapter + SliceAccess> Formatter<'a, T> {
             .parse(buf.slice(0..).as_bytes(), None)
             .expect("should parse markdown doc");
 
-        Self { buf, tree }
+        Self {
+            buf,
+            tree,
+            width: DEFAULT_WRAP_WIDTH,
+        }
+    }
+
+    /// Wrap lines at `width` instead of `DEFAULT_WRAP_WIDTH`.
+    pub fn wrap_width(mut self, width: usize) -> Self {
+        self.width = width;
+        self
+    }
+
+    /// The whole buffer with every paragraph and list formatted.
+    pub fn format_all(&self) -> String {
+        let src = self.buf.slice(0..);
+        let mut ret = String::with_capacity(src.len());
+        let mut last = 0;
+        let cursor = self.tree.root_node().walk();
+        for it in Traversal::from_cursor(cursor) {
+            // Rewrapping would drop the `>` markers of continuation lines.
+            if in_block_quote(it) {
+                continue;
+            }
+            let r1 = it.byte_range();
+            let src2 = src[r1.clone()].trim_end();
+            let formatted = match it.kind() {
+                "paragraph" => process_section(src2, self.width),
+                "list" => process_list_items(src2, self.width),
+                _ => continue,
+            };
+            ret.push_str(&src[last..r1.start]);
+            ret.push_str(&formatted);
+            last = r1.start + src2.len();
+        }
+        ret.push_str(&src[last..]);
+        ret
     }
 
     fn range_from_lsp(&self, range: LspRange) -> Range<usize> {
@@ -38,6 +78,17 @@ impl<'a, T: LspAdapter + SliceAccess> Formatter<'a, T> {
     }
 }
 
+fn in_block_quote(node: Node<'_>) -> bool {
+    let mut it = node.parent();
+    while let Some(parent) = it {
+        if parent.kind() == "block_quote" {
+            return true;
+        }
+        it = parent.parent();
+    }
+    false
+}
+
 /// Simple utility to convert between LSP and treesitter positions.
 struct MyPosition(LspPosition);
 
@@ -59,13 +110,14 @@ impl Into<LspPosition> for MyPosition {
 fn process_list_node<T: SliceAccess>(
     buf: &T,
     node: Node<'_>,
+    width: usize,
 ) -> impl Iterator<Item = TextEdit> {
     let r1 = node.byte_range();
     let src = buf.slice(r1.clone());
     let src2 = src.trim_end();
     let src3 = &src[src2.len()..];
 
-    let mut ret = process_list_items(src2);
+    let mut ret = process_list_items(src2, width);
     ret.push_str(src3);
 
     iter::once(TextEdit {
@@ -98,7 +150,7 @@ impl<'a, T: LspAdapter + SliceAccess> LspRangeFormat for Formatter<'a, T> {
                     let src = self.buf.slice(r1.clone());
                     let src2 = src.trim_end();
                     let src3 = &src[src2.len()..];
-                    let mut updated = process_section(&src2);
+                    let mut updated = process_section(&src2, self.width);
                     updated.push_str(src3);
                     res =
                         Box::new(
@@ -116,7 +168,9 @@ impl<'a, T: LspAdapter + SliceAccess> LspRangeFormat for Formatter<'a, T> {
                         );
                 },
                 "list" => {
-                    res = Box::new(res.chain(process_list_node(self.buf, it)));
+                    res = Box::new(
+                        res.chain(process_list_node(self.buf, it, self.width)),
+                    );
                     // dbg!(&self.buf.slice(r1.clone()));
                     // let updated = process_list_items(&self.buf.slice(r1));
                     // dbg!(updated);
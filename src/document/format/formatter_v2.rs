@@ -4,7 +4,9 @@ use tower_lsp::lsp_types::{
 };
 use tree_sitter::{InputEdit, Parser, Point, Tree};
 
-use crate::document::document::{BasicDocument, Section, SliceAccess};
+use crate::document::document::{
+    BasicDocument, Section, SliceAccess, SyntaxTree,
+};
 use crate::document::document_adapter::LspAdapter;
 use crate::document::incremental_sync::IncrementalSync;
 
@@ -38,6 +40,12 @@ pub struct Formatter {
     tree: Tree,
 }
 
+impl SyntaxTree for Formatter {
+    fn tree(&self) -> &Tree {
+        &self.tree
+    }
+}
+
 impl Formatter {
     pub fn new(buf: Rope) -> Self {
         let lang = tree_sitter_md::language();
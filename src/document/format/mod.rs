@@ -15,5 +15,6 @@ pub trait LspRangeFormat {
     fn format(&self, range: Range) -> Option<Vec<TextEdit>>;
 }
 /// A formatter that uses the treesitter library to format documents.
-pub use format_treesitter::Formatter;
+pub use format_treesitter::{Formatter, DEFAULT_WRAP_WIDTH};
 pub use formatter_v2::Formatter as FormatterV2;
+pub use treesitter::Traversal;
@@ -18,7 +18,7 @@ fn list_item() -> &'static Regex {
         .get_or_init(|| Regex::new(r#"(^\s*[-*] )|(\n\s*[-*] )"#).unwrap());
 }
 
-pub fn process_list_items(section: &str) -> String {
+pub fn process_list_items(section: &str, width: usize) -> String {
     let words = non_ws();
 
     let mut ret = String::with_capacity(8192);
@@ -46,7 +46,7 @@ pub fn process_list_items(section: &str) -> String {
         for m in words {
             let is_url = url().is_match(m.as_str());
             if line_len != line_indent &&
-                (line_len + m.as_str().len() > 80 || is_url || was_url)
+                (line_len + m.as_str().len() > width || is_url || was_url)
             {
                 ret.pop();
                 ret.push('\n');
@@ -71,7 +71,7 @@ pub fn process_list_items(section: &str) -> String {
 }
 
 /// Process a section chunk which is a part of a section.
-fn process_section_chunk(section: &str) -> String {
+fn process_section_chunk(section: &str, width: usize) -> String {
     let ws = non_ws();
     let words = ws.find_iter(section);
     let mut ret = String::with_capacity(8192);
@@ -80,7 +80,7 @@ fn process_section_chunk(section: &str) -> String {
     for m in words {
         let is_url = url().is_match(m.as_str());
         if line_len != 0 &&
-            (line_len + m.as_str().len() > 80 || is_url || was_url)
+            (line_len + m.as_str().len() > width || is_url || was_url)
         {
             ret.pop();
             ret.push('\n');
@@ -97,14 +97,16 @@ fn process_section_chunk(section: &str) -> String {
     ret
 }
 
-pub fn process_section(section: &str) -> String {
+/// Rewrap a section so lines are at most `width` characters, unless a word
+/// or URL does not fit.
+pub fn process_section(section: &str, width: usize) -> String {
     if list_item().is_match(section) {
-        return process_list_items(section);
+        return process_list_items(section, width);
     }
 
     section
         .split("  \n")
-        .map(|chunk| process_section_chunk(chunk))
+        .map(|chunk| process_section_chunk(chunk, width))
         .fold(String::with_capacity(8192), |mut acc, it| {
             if !acc.is_empty() {
                 acc.push_str("  \n");
@@ -122,6 +122,25 @@ fn format_should_keep_line_breaks() -> anyhow::Result<()> {
     Ok(())
 }
 
+#[test]
+fn format_should_wrap_at_configured_width() -> anyhow::Result<()> {
+    let src = "one two three four five six\n";
+    let doc = TestDoc::new(src);
+    let range = Range {
+        start: Position {
+            line: 0,
+            character: 0,
+        },
+        end: Position {
+            line: 1,
+            character: 0,
+        },
+    };
+    let edits = Formatter::new(&doc).wrap_width(14).format(range).unwrap();
+    assert_eq!("one two three\nfour five six\n", doc.apply_edits(edits));
+    Ok(())
+}
+
 #[test]
 #[ignore = "currently this test is failing"]
 fn format_should_remove_whitespace_at_the_beginning() -> anyhow::Result<()> {
@@ -147,7 +166,7 @@ fn format_should_remove_whitespace_at_the_beginning() -> anyhow::Result<()> {
 fn process_section_should_format_properly() {
     assert_eq!(
         "a\nsomereallylongstringisnotabletoformattomultiplelinestheyshoujldkeptsinglelineasisb\nahblahhaha1234567", 
-        process_section(r#"a somereallylongstringisnotabletoformattomultiplelinestheyshoujldkeptsinglelineasisb ahblahhaha1234567"#)
+        process_section(r#"a somereallylongstringisnotabletoformattomultiplelinestheyshoujldkeptsinglelineasisb ahblahhaha1234567"#, 80)
     );
 }
 
@@ -155,7 +174,7 @@ fn process_section_should_format_properly() {
 fn process_section_should_format_url() {
     assert_eq!(
         "a:\nhttps://someurl.com\nahblahhaha1234567",
-        process_section(r#"a: https://someurl.com ahblahhaha1234567"#)
+        process_section(r#"a: https://someurl.com ahblahhaha1234567"#, 80)
     );
 }
 
@@ -182,6 +201,6 @@ fn process_section_bullet_items() {
   is good
 - item 5"#
             .trim(),
-        process_section(src)
+        process_section(src, 80)
     );
 }
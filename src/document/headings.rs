@@ -0,0 +1,100 @@
+use std::ops::Range;
+
+use tree_sitter::Node;
+
+use super::document::SyntaxTree;
+
+/// A heading at any nesting level, with the byte range of its section.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Heading {
+    pub level: usize,
+    pub title: Range<usize>,
+    pub line: Range<usize>,
+    pub range: Range<usize>,
+}
+
+/// Collect every ATX or setext heading in document order.
+pub fn headings<D: SyntaxTree>(doc: &D) -> Vec<Heading> {
+    let mut ret = Vec::new();
+    collect(doc.tree().root_node(), &mut ret);
+    ret
+}
+
+fn collect(node: Node<'_>, ret: &mut Vec<Heading>) {
+    match node.kind() {
+        "section" => {
+            let first = node.child(0).filter(|it| it.kind() == "atx_heading");
+            if let Some(it) = first {
+                ret.extend(heading(it, node.byte_range()));
+            }
+        },
+        // Setext headings do not open a section of their own, so they span
+        // up to the next heading or the end of the enclosing block.
+        "setext_heading" => {
+            let mut end = node.next_sibling();
+            while let Some(it) = end {
+                if it.kind() == "section" || it.kind() == "setext_heading" {
+                    break;
+                }
+                end = it.next_sibling();
+            }
+            let end = end
+                .map(|it| it.start_byte())
+                .or_else(|| node.parent().map(|it| it.end_byte()))
+                .unwrap_or(node.end_byte());
+            ret.extend(heading(node, node.start_byte()..end));
+        },
+        _ => {},
+    }
+
+    let mut cursor = node.walk();
+    for child in node.named_children(&mut cursor) {
+        collect(child, ret);
+    }
+}
+
+fn heading(node: Node<'_>, range: Range<usize>) -> Option<Heading> {
+    let mut cursor = node.walk();
+    let level = node.children(&mut cursor).find_map(|it| match it.kind() {
+        "atx_h1_marker" | "setext_h1_underline" => Some(1),
+        "atx_h2_marker" | "setext_h2_underline" => Some(2),
+        "atx_h3_marker" => Some(3),
+        "atx_h4_marker" => Some(4),
+        "atx_h5_marker" => Some(5),
+        "atx_h6_marker" => Some(6),
+        _ => None,
+    })?;
+    let title = node.child_by_field_name("heading_content")?.byte_range();
+
+    Some(Heading {
+        level,
+        title,
+        line: node.byte_range(),
+        range,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::Document;
+
+    #[test]
+    fn headings_should_include_nested_sections() -> anyhow::Result<()> {
+        let src = "# Top\n\nIntro\n\n## Sub\n\nText\n\nSetext\n---\n\nMore\n";
+        let doc = Document::parse(src)?;
+        let res = headings(&doc);
+
+        assert_eq!(
+            vec![(1, "Top"), (2, "Sub"), (2, "Setext")],
+            res.iter()
+                .map(|h| (h.level, src[h.title.clone()].trim()))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(0..src.len(), res[0].range);
+        assert_eq!(14..src.len(), res[1].range);
+        assert_eq!(28..src.len(), res[2].range);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,382 @@
+use std::cmp::{Ordering, Reverse};
+use std::collections::{BinaryHeap, HashMap, HashSet};
+use std::hash::Hash;
+
+use serde::{Deserialize, Serialize};
+
+use super::bert::{Embedding, DIMENSIONS};
+
+/// Max neighbours per node on upper layers; layer 0 keeps twice as many.
+const M: usize = 16;
+const EF_CONSTRUCTION: usize = 64;
+
+/// Hierarchical navigable small world graph for approximate nearest
+/// neighbour search over normalized embeddings.
+///
+/// Removed nodes are kept as tombstones so the graph stays navigable, and the
+/// graph is rebuilt once they make up half of it. Vectors are not serialized;
+/// call `restore` after deserializing.
+#[derive(Serialize, Deserialize)]
+pub struct Hnsw<K: Eq + Hash> {
+    nodes: Vec<Node<K>>,
+    entry: Option<usize>,
+    deleted: usize,
+    seed: u64,
+    #[serde(skip)]
+    vectors: Vec<Embedding>,
+    #[serde(skip)]
+    ids: HashMap<K, usize>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct Node<K> {
+    key: K,
+    layers: Vec<Vec<usize>>,
+    deleted: bool,
+}
+
+/// Similarity paired with node id, ordered by similarity.
+#[derive(PartialEq, Clone, Copy)]
+struct Scored(f32, usize);
+
+impl Eq for Scored {}
+
+impl PartialOrd for Scored {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Scored {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0).then(self.1.cmp(&other.1))
+    }
+}
+
+impl<K: Eq + Hash> Default for Hnsw<K> {
+    fn default() -> Self {
+        Self {
+            nodes: Vec::new(),
+            entry: None,
+            deleted: 0,
+            seed: 0x2545_f491_4f6c_dd1d,
+            vectors: Vec::new(),
+            ids: HashMap::new(),
+        }
+    }
+}
+
+impl<K: Clone + Eq + Hash> Hnsw<K> {
+    /// Number of live entries.
+    pub fn len(&self) -> usize {
+        self.nodes.len() - self.deleted
+    }
+
+    pub fn insert(&mut self, key: K, embedding: Embedding) {
+        self.remove(&key);
+
+        let id = self.nodes.len();
+        let level = self.random_level();
+        self.nodes.push(Node {
+            key: key.clone(),
+            layers: vec![Vec::new(); level + 1],
+            deleted: false,
+        });
+        self.vectors.push(embedding);
+        self.ids.insert(key, id);
+
+        let Some(entry) = self.entry else {
+            self.entry = Some(id);
+            return;
+        };
+        let top = self.nodes[entry].layers.len() - 1;
+        let mut eps = vec![entry];
+        for layer in (level + 1..=top).rev() {
+            eps = self.search_layer(&self.vectors[id], &eps, 1, layer);
+        }
+        for layer in (0..=level.min(top)).rev() {
+            let found = self.search_layer(
+                &self.vectors[id],
+                &eps,
+                EF_CONSTRUCTION,
+                layer,
+            );
+            let max = if layer == 0 { 2 * M } else { M };
+            for &n in found.iter().take(M) {
+                self.nodes[id].layers[layer].push(n);
+                self.nodes[n].layers[layer].push(id);
+                self.prune(n, layer, max);
+            }
+            eps = found;
+        }
+        if level > top {
+            self.entry = Some(id);
+        }
+    }
+
+    pub fn remove(&mut self, key: &K) {
+        let Some(id) = self.ids.remove(key) else {
+            return;
+        };
+        self.nodes[id].deleted = true;
+        self.deleted += 1;
+        if self.deleted * 2 > self.nodes.len() {
+            self.rebuild();
+        }
+    }
+
+    /// Copy of the graph, without vectors, with entries not matching `keep`
+    /// marked removed. For serializing part of the graph; call `restore` on
+    /// the deserialized copy as usual.
+    pub fn retained(&self, keep: impl Fn(&K) -> bool) -> Self {
+        let nodes: Vec<Node<K>> = self
+            .nodes
+            .iter()
+            .map(|it| Node {
+                deleted: it.deleted || !keep(&it.key),
+                ..it.clone()
+            })
+            .collect();
+        Self {
+            deleted: nodes.iter().filter(|it| it.deleted).count(),
+            nodes,
+            entry: self.entry,
+            seed: self.seed,
+            vectors: Vec::new(),
+            ids: HashMap::new(),
+        }
+    }
+
+    /// Up to `k` live entries most similar to the query. Larger `ef` trades
+    /// latency for recall.
+    pub fn search(
+        &self,
+        query: &Embedding,
+        k: usize,
+        ef: usize,
+    ) -> Vec<(K, f32)> {
+        let Some(entry) = self.entry else {
+            return Vec::new();
+        };
+        let mut eps = vec![entry];
+        for layer in (1..self.nodes[entry].layers.len()).rev() {
+            eps = self.search_layer(query, &eps, 1, layer);
+        }
+        self.search_layer(query, &eps, ef.max(k), 0)
+            .into_iter()
+            .filter(|&id| !self.nodes[id].deleted)
+            .take(k)
+            .map(|id| (self.nodes[id].key.clone(), self.vectors[id].cos(query)))
+            .collect()
+    }
+
+    /// Exact top `k` by linear scan, the reference for measuring recall.
+    #[cfg(test)]
+    pub fn search_exact(&self, query: &Embedding, k: usize) -> Vec<(K, f32)> {
+        let mut ret: Vec<(K, f32)> = self
+            .ids
+            .iter()
+            .map(|(key, &id)| (key.clone(), self.vectors[id].cos(query)))
+            .collect();
+        ret.sort_by(|a, b| b.1.total_cmp(&a.1));
+        ret.truncate(k);
+        ret
+    }
+
+    /// Reattach vectors after deserialization. Returns false if a live entry
+    /// has no vector, in which case the graph should be rebuilt.
+    pub fn restore(
+        &mut self,
+        lookup: impl Fn(&K) -> Option<Embedding>,
+    ) -> bool {
+        self.ids.clear();
+        self.vectors.clear();
+        for (id, node) in self.nodes.iter().enumerate() {
+            match lookup(&node.key) {
+                Some(it) => self.vectors.push(it),
+                None if node.deleted => {
+                    self.vectors.push(Embedding::new(vec![0.0; DIMENSIONS]))
+                },
+                None => return false,
+            }
+            if !node.deleted {
+                self.ids.insert(node.key.clone(), id);
+            }
+        }
+        true
+    }
+
+    /// Greedy best-first search on one layer; returns ids by descending
+    /// similarity.
+    fn search_layer(
+        &self,
+        query: &Embedding,
+        eps: &[usize],
+        ef: usize,
+        layer: usize,
+    ) -> Vec<usize> {
+        let mut visited: HashSet<usize> = eps.iter().copied().collect();
+        let mut candidates: BinaryHeap<Scored> = BinaryHeap::new();
+        let mut found: BinaryHeap<Reverse<Scored>> = BinaryHeap::new();
+        for &ep in eps {
+            let it = Scored(self.vectors[ep].cos(query), ep);
+            candidates.push(it);
+            found.push(Reverse(it));
+        }
+        while found.len() > ef {
+            found.pop();
+        }
+
+        while let Some(Scored(sim, id)) = candidates.pop() {
+            let worst = found.peek().map_or(f32::MIN, |it| it.0 .0);
+            if sim < worst && found.len() >= ef {
+                break;
+            }
+            let Some(neighbours) = self.nodes[id].layers.get(layer) else {
+                continue;
+            };
+            for &n in neighbours {
+                if !visited.insert(n) {
+                    continue;
+                }
+                let it = Scored(self.vectors[n].cos(query), n);
+                let worst = found.peek().map_or(f32::MIN, |it| it.0 .0);
+                if found.len() < ef || it.0 > worst {
+                    candidates.push(it);
+                    found.push(Reverse(it));
+                    if found.len() > ef {
+                        found.pop();
+                    }
+                }
+            }
+        }
+
+        let mut ret: Vec<Scored> = found.into_iter().map(|it| it.0).collect();
+        ret.sort_by(|a, b| b.cmp(a));
+        ret.into_iter().map(|it| it.1).collect()
+    }
+
+    /// Keep the `max` most similar neighbours of a node.
+    fn prune(&mut self, id: usize, layer: usize, max: usize) {
+        if self.nodes[id].layers[layer].len() <= max {
+            return;
+        }
+        let mut scored: Vec<Scored> = self.nodes[id].layers[layer]
+            .iter()
+            .map(|&n| Scored(self.vectors[n].cos(&self.vectors[id]), n))
+            .collect();
+        scored.sort_by(|a, b| b.cmp(a));
+        self.nodes[id].layers[layer] =
+            scored.into_iter().take(max).map(|it| it.1).collect();
+    }
+
+    fn rebuild(&mut self) {
+        let nodes = std::mem::take(&mut self.nodes);
+        let vectors = std::mem::take(&mut self.vectors);
+        let seed = self.seed;
+        *self = Self::default();
+        self.seed = seed;
+        for (node, vector) in nodes.into_iter().zip(vectors) {
+            if !node.deleted {
+                self.insert(node.key, vector);
+            }
+        }
+    }
+
+    /// Exponentially distributed level with normalization 1 / ln(M).
+    fn random_level(&mut self) -> usize {
+        // xorshift64
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 7;
+        self.seed ^= self.seed << 17;
+        let uniform = (self.seed >> 11) as f64 / (1u64 << 53) as f64;
+        (-(1.0 - uniform).ln() / (M as f64).ln()) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn random_embeddings(count: usize, mut seed: u64) -> Vec<Embedding> {
+        (0..count)
+            .map(|_| {
+                let v = (0..384)
+                    .map(|_| {
+                        seed ^= seed << 13;
+                        seed ^= seed >> 7;
+                        seed ^= seed << 17;
+                        (seed % 2000) as f32 / 1000.0 - 1.0
+                    })
+                    .collect();
+                Embedding::new(v)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn search_should_match_brute_force() {
+        let mut index = Hnsw::default();
+        for (i, it) in random_embeddings(1000, 42).into_iter().enumerate() {
+            index.insert(i, it);
+        }
+
+        let queries = random_embeddings(20, 7);
+        let mut hits = 0;
+        for query in &queries {
+            let exact: HashSet<usize> = index
+                .search_exact(query, 10)
+                .into_iter()
+                .map(|it| it.0)
+                .collect();
+            hits += index
+                .search(query, 10, 64)
+                .into_iter()
+                .filter(|it| exact.contains(&it.0))
+                .count();
+        }
+
+        let recall = hits as f32 / (queries.len() * 10) as f32;
+        assert!(recall >= 0.9, "recall: {}", recall);
+    }
+
+    #[test]
+    fn removed_entries_should_not_be_returned() {
+        let embeddings = random_embeddings(100, 3);
+        let mut index = Hnsw::default();
+        for (i, it) in embeddings.iter().enumerate() {
+            index.insert(i, it.clone());
+        }
+
+        index.remove(&5);
+        assert_eq!(99, index.len());
+        assert!(index
+            .search(&embeddings[5], 10, 64)
+            .iter()
+            .all(|it| it.0 != 5));
+
+        for i in 0..60 {
+            index.remove(&i);
+        }
+        assert_eq!(40, index.len());
+        assert_eq!(60, index.search(&embeddings[60], 1, 64)[0].0);
+    }
+
+    #[test]
+    fn restore_should_reattach_vectors() -> anyhow::Result<()> {
+        let embeddings = random_embeddings(50, 11);
+        let mut index = Hnsw::default();
+        for (i, it) in embeddings.iter().enumerate() {
+            index.insert(i, it.clone());
+        }
+
+        let mut buf = Vec::<u8>::new();
+        ciborium::into_writer(&index, &mut buf)?;
+        let mut decoded: Hnsw<usize> = ciborium::from_reader(buf.as_slice())?;
+        assert!(decoded.restore(|&i| embeddings.get(i).cloned()));
+        assert_eq!(7, decoded.search(&embeddings[7], 1, 16)[0].0);
+        assert!(!decoded.restore(|_| None));
+
+        Ok(())
+    }
+}
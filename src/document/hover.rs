@@ -0,0 +1,207 @@
+use tower_lsp::lsp_types::{
+    Hover, HoverContents, MarkupContent, MarkupKind, Position, Range, Url,
+};
+
+use super::document::{BasicDocument, SliceAccess};
+use super::document_adapter::LspAdapter;
+use super::headings::{headings, Heading};
+use super::keyword_index::KeywordIndex;
+use super::links::{
+    backlinks, find_heading, footnote_at, footnote_definition, links, slugify,
+};
+use super::workspace::Workspace;
+use super::Document;
+
+/// Number of section lines shown when previewing a link target.
+const PREVIEW_LINES: usize = 10;
+
+/// Hover contents for the footnote reference, link or heading under the
+/// cursor. Heading keywords come from the keyword index and are left out
+/// until extracted, e.g. by the keyword lens.
+pub fn hover(
+    uri: &Url,
+    doc: &Document,
+    workspace: &Workspace,
+    keywords: &KeywordIndex,
+    pos: &Position,
+) -> Option<Hover> {
+    let offset = doc.position_to_offset(pos)?;
+
+    hover_footnote(doc, offset)
+        .or_else(|| hover_link(uri, doc, workspace, offset))
+        .or_else(|| hover_heading(uri, doc, workspace, keywords, offset))
+}
+
+fn hover_footnote(doc: &Document, offset: usize) -> Option<Hover> {
+    let label = footnote_at(doc, offset)?;
+    let text = footnote_definition(doc, &label)?;
+    Some(markdown(format!("**[^{}]**: {}", label, text), None))
+}
+
+fn hover_link(
+    uri: &Url,
+    doc: &Document,
+    workspace: &Workspace,
+    offset: usize,
+) -> Option<Hover> {
+    let link = links(doc)
+        .into_iter()
+        .find(|it| it.range.contains(&offset))?;
+    let target = link.resolve(uri)?;
+    let range = lsp_range(doc, link.range.start, link.range.end);
+
+    let preview = |target_doc: &Document| {
+        let heading = match &link.anchor {
+            Some(anchor) => Some(find_heading(target_doc, anchor)?),
+            None => None,
+        };
+        Some(preview(&target, target_doc, heading.as_ref()))
+    };
+    let value = if &target == uri {
+        preview(doc)
+    } else {
+        preview(workspace.get(&target)?.value())
+    }?;
+
+    Some(markdown(value, range))
+}
+
+fn hover_heading(
+    uri: &Url,
+    doc: &Document,
+    workspace: &Workspace,
+    keywords: &KeywordIndex,
+    offset: usize,
+) -> Option<Hover> {
+    let heading = headings(doc)
+        .into_iter()
+        .find(|it| it.line.contains(&offset))?;
+    let title = doc.slice(heading.title.clone()).trim().to_string();
+    let text = doc.slice(heading.range.clone());
+    let words = text.split_whitespace().count();
+    let keywords: Vec<String> = doc
+        .sections()
+        .iter()
+        .position(|it| it.range.contains(&heading.line.start))
+        .and_then(|section| keywords.cached(uri, doc, section))
+        .unwrap_or_default()
+        .into_iter()
+        .map(|it| it.text)
+        .collect();
+    let inbound = backlinks(workspace, uri, &slugify(&title)).len();
+
+    let value = format!(
+        "**{}**\n\nWords: {} · Inbound links: {}\n\nKeywords: {}",
+        title,
+        words,
+        inbound,
+        keywords.join(", ")
+    );
+    let range = lsp_range(doc, heading.line.start, heading.line.end);
+
+    Some(markdown(value, range))
+}
+
+/// Title followed by the first lines of the heading's section, or of the
+/// whole document when no heading is given.
+fn preview(uri: &Url, doc: &Document, heading: Option<&Heading>) -> String {
+    let name = uri
+        .path_segments()
+        .and_then(|mut it| it.next_back())
+        .unwrap_or_default()
+        .to_string();
+    let (title, body) = match heading {
+        Some(heading) => (
+            format!("{} › {}", name, doc.slice(heading.title.clone()).trim()),
+            doc.slice(heading.line.end..heading.range.end),
+        ),
+        None => (name, doc.slice(0..)),
+    };
+    let body = body
+        .trim_start_matches('\n')
+        .lines()
+        .take(PREVIEW_LINES)
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    format!("**{}**\n\n{}", title, body)
+}
+
+fn lsp_range(doc: &Document, start: usize, end: usize) -> Option<Range> {
+    Some(Range::new(
+        doc.offset_to_position(start)?,
+        doc.offset_to_position(end)?,
+    ))
+}
+
+fn markdown(value: String, range: Option<Range>) -> Hover {
+    Hover {
+        contents: HoverContents::Markup(MarkupContent {
+            kind: MarkupKind::Markdown,
+            value,
+        }),
+        range,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn value(hover: Hover) -> String {
+        match hover.contents {
+            HoverContents::Markup(it) => it.value,
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn hover_link_should_preview_target_section() -> anyhow::Result<()> {
+        let workspace = Workspace::default();
+        let uri = Url::parse("file:///notes/index.md")?;
+        let other = Url::parse("file:///notes/other.md")?;
+        let doc =
+            Document::parse("# Index\n\nSee [it](other.md#second-part).\n")?;
+        workspace.insert(
+            &other,
+            Document::parse(
+                "# First\n\nA\n\n## Second part\n\nLine 1\nLine 2\n",
+            )?,
+        );
+
+        let res = hover_link(&uri, &doc, &workspace, 15).unwrap();
+        assert_eq!("**other.md › Second part**\n\nLine 1\nLine 2", value(res));
+        assert_eq!(None, hover_link(&uri, &doc, &workspace, 2));
+
+        Ok(())
+    }
+
+    #[test]
+    fn hover_footnote_should_show_definition() -> anyhow::Result<()> {
+        let doc = Document::parse("Text[^1].\n\n[^1]: Footnote text.\n")?;
+
+        let res = hover_footnote(&doc, 5).unwrap();
+        assert_eq!("**[^1]**: Footnote text.", value(res));
+
+        Ok(())
+    }
+
+    #[test]
+    fn hover_heading_should_count_inbound_links() -> anyhow::Result<()> {
+        let workspace = Workspace::default();
+        let uri = Url::parse("file:///notes/index.md")?;
+        let text = "# Index\n\n## Topic\n\n[self](#topic)\n";
+        let doc = Document::parse(text)?;
+        workspace.insert(&uri, Document::parse(text)?);
+        workspace.insert(
+            &Url::parse("file:///notes/a.md")?,
+            Document::parse("[x](index.md#Topic) [y](index.md#other)\n")?,
+        );
+
+        let keywords = KeywordIndex::default();
+        let res = hover_heading(&uri, &doc, &workspace, &keywords, 10).unwrap();
+        assert!(value(res).contains("Inbound links: 2"));
+
+        Ok(())
+    }
+}
@@ -1,10 +1,11 @@
 use std::fs;
 use std::str::FromStr;
 
-use tower_lsp::lsp_types::{Position, Url};
+use tower_lsp::lsp_types::{Location, Position, Range, Url};
 
-use super::{BertModel, Document};
+use super::{BertModel, Document, SearchOptions, SectionIndex, Workspace};
 use crate::document::find_similar;
+use crate::document::section_index::Granularity;
 
 struct TestSubject {
     pub model: BertModel,
@@ -25,14 +26,57 @@ fn prepare_subject() -> anyhow::Result<TestSubject> {
 fn test_find_similar() -> anyhow::Result<()> {
     let TestSubject { model, document } = prepare_subject()?;
 
+    let uri = Url::from_str("test://file")?;
+    let workspace = Workspace::default();
+    workspace.insert(&uri, document);
+
+    let pos = Position::new(1, 0);
     let tmp = find_similar(
-        Url::from_str("test://file")?,
-        &document,
+        &workspace,
+        &SectionIndex::default(),
         &model,
-        &Position::new(1, 0),
-    );
+        &Location::new(uri, Range::new(pos, pos)),
+        &SearchOptions::default(),
+    )?;
 
     dbg!(tmp);
 
     Ok(())
 }
+
+#[test]
+fn test_find_similar_paragraphs() -> anyhow::Result<()> {
+    let model = BertModel::default();
+    let uri = Url::from_str("test://file")?;
+    let workspace = Workspace::default();
+    workspace.insert(
+        &uri,
+        Document::parse(
+            "# Rust\n\nThe borrow checker rejects aliasing.\n\n- Cook pasta \
+             in salted water\n\n# Notes\n\nBorrow checker errors again.\n",
+        )?,
+    );
+
+    let pos = Position::new(2, 4);
+    let opts = SearchOptions {
+        granularity: Granularity::Paragraph,
+        ..Default::default()
+    };
+    let res = find_similar(
+        &workspace,
+        &SectionIndex::default(),
+        &model,
+        &Location::new(uri, Range::new(pos, pos)),
+        &opts,
+    )?;
+
+    // The paragraph under the cursor is not a result.
+    assert_eq!(2, res.len());
+    assert_eq!("Notes", res[0].title);
+    assert_eq!(
+        Range::new(Position::new(8, 0), Position::new(8, 28)),
+        res[0].location.range
+    );
+
+    Ok(())
+}
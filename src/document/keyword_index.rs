@@ -0,0 +1,216 @@
+use std::borrow::Cow;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashMap};
+
+use dashmap::DashMap;
+use serde::Serialize;
+use tower_lsp::lsp_types::{Location, Url};
+
+use super::bert::{Keyword, Keywords};
+use super::document::{BasicDocument, DocumentExt};
+use super::document_adapter::DocumentLsp;
+use super::section_index::hash;
+use super::workspace::Workspace;
+use super::{Document, ScoredLocation};
+
+/// Keywords of every section, re-extracted only when a section's content
+/// changes.
+#[derive(Default)]
+pub struct KeywordIndex {
+    documents: DashMap<String, Vec<SectionKeywords>>,
+}
+
+#[derive(Clone)]
+struct SectionKeywords {
+    hash: u64,
+    keywords: Vec<Keyword>,
+}
+
+/// A keyword and the sections it was extracted from, scored by its relevance
+/// to each section.
+#[derive(Debug, Serialize)]
+pub struct KeywordEntry {
+    pub keyword: String,
+    pub sections: Vec<ScoredLocation<'static>>,
+}
+
+impl KeywordIndex {
+    /// Keywords of each section of the document, most relevant first.
+    pub fn keywords(
+        &self,
+        uri: &Url,
+        doc: &Document,
+        enc: &impl Keywords,
+    ) -> anyhow::Result<Vec<Vec<Keyword>>> {
+        let texts: Vec<String> = (0..doc.sections().len())
+            .map(|i| Ok(DocumentExt::text(doc, i)?.into_owned()))
+            .collect::<anyhow::Result<_>>()?;
+        let hashes: Vec<u64> = texts.iter().map(|it| hash(it)).collect();
+
+        let mut cached: HashMap<u64, Vec<Keyword>> = self
+            .documents
+            .get(uri.as_str())
+            .map(|it| {
+                it.iter().map(|it| (it.hash, it.keywords.clone())).collect()
+            })
+            .unwrap_or_default();
+        let missing: Vec<usize> = (0..texts.len())
+            .filter(|&i| !cached.contains_key(&hashes[i]))
+            .collect();
+        if !missing.is_empty() {
+            let batch: Vec<&str> =
+                missing.iter().map(|&i| texts[i].as_str()).collect();
+            for (i, keywords) in missing.iter().zip(enc.extract_batch(&batch)?)
+            {
+                cached.insert(hashes[*i], keywords);
+            }
+        }
+
+        let sections: Vec<SectionKeywords> = hashes
+            .iter()
+            .map(|it| SectionKeywords {
+                hash: *it,
+                keywords: cached[it].clone(),
+            })
+            .collect();
+        let ret = sections.iter().map(|it| it.keywords.clone()).collect();
+        self.documents.insert(uri.to_string(), sections);
+        Ok(ret)
+    }
+
+    /// Keywords of a section already extracted for its current content,
+    /// without running the model.
+    pub fn cached(
+        &self,
+        uri: &Url,
+        doc: &Document,
+        section: usize,
+    ) -> Option<Vec<Keyword>> {
+        let hash = hash(&DocumentExt::text(doc, section).ok()?);
+        self.documents
+            .get(uri.as_str())?
+            .iter()
+            .find(|it| it.hash == hash)
+            .map(|it| it.keywords.clone())
+    }
+
+    pub fn remove(&self, uri: &Url) {
+        self.documents.remove(uri.as_str());
+    }
+
+    /// Every keyword in the workspace with its sections, keywords found in
+    /// the most sections first.
+    pub fn entries(
+        &self,
+        workspace: &Workspace,
+        enc: &impl Keywords,
+    ) -> anyhow::Result<Vec<KeywordEntry>> {
+        let mut entries: BTreeMap<String, Vec<ScoredLocation<'static>>> =
+            BTreeMap::new();
+        for uri in workspace.uris() {
+            let Some(doc) = workspace.get(&uri) else {
+                continue;
+            };
+            let doc = doc.value();
+            for (i, keywords) in
+                self.keywords(&uri, doc, enc)?.into_iter().enumerate()
+            {
+                let Some(range) = doc.section_to_title_range(i) else {
+                    continue;
+                };
+                let title = DocumentExt::title(doc, i)?.into_owned();
+                for keyword in keywords {
+                    entries
+                        .entry(keyword.text.to_lowercase())
+                        .or_default()
+                        .push(ScoredLocation {
+                            score: keyword.score,
+                            title: Cow::Owned(title.clone()),
+                            location: Location::new(uri.clone(), range),
+                            vector_score: None,
+                            lexical_score: None,
+                            chunk: None,
+                        });
+                }
+            }
+        }
+
+        let mut ret: Vec<KeywordEntry> = entries
+            .into_iter()
+            .map(|(keyword, mut sections)| {
+                sections.sort_by(|a, b| b.score.total_cmp(&a.score));
+                KeywordEntry { keyword, sections }
+            })
+            .collect();
+        ret.sort_by_key(|it| Reverse(it.sections.len()));
+        Ok(ret)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use super::*;
+    use crate::document::BertModel;
+
+    /// Counts the texts it extracts keywords from.
+    #[derive(Default)]
+    struct Counting {
+        model: BertModel,
+        texts: AtomicUsize,
+    }
+
+    impl Keywords for Counting {
+        fn extract_batch(
+            &self,
+            texts: &[&str],
+        ) -> anyhow::Result<Vec<Vec<Keyword>>> {
+            self.texts.fetch_add(texts.len(), Ordering::SeqCst);
+            self.model.extract_batch(texts)
+        }
+    }
+
+    #[test]
+    fn keywords_should_be_extracted_for_changed_sections_only(
+    ) -> anyhow::Result<()> {
+        let uri = Url::parse("file:///notes/a.md")?;
+        let index = KeywordIndex::default();
+        let enc = Counting::default();
+
+        let doc = Document::parse(
+            "# Rust\n\nCargo crates.\n\n# Tea\n\nGreen tea.\n",
+        )?;
+        assert_eq!(2, index.keywords(&uri, &doc, &enc)?.len());
+        assert_eq!(2, enc.texts.load(Ordering::SeqCst));
+
+        let doc = Document::parse(
+            "# Rust\n\nCargo crates.\n\n# Tea\n\nGreen tea, hot.\n",
+        )?;
+        index.keywords(&uri, &doc, &enc)?;
+        assert_eq!(3, enc.texts.load(Ordering::SeqCst));
+
+        Ok(())
+    }
+
+    #[test]
+    fn entries_should_list_sections_per_keyword() -> anyhow::Result<()> {
+        let workspace = Workspace::default();
+        workspace.insert(
+            &Url::parse("file:///notes/a.md")?,
+            Document::parse("# Cargo\n\nCargo crates.\n")?,
+        );
+        workspace.insert(
+            &Url::parse("file:///notes/b.md")?,
+            Document::parse("# Build\n\nCargo builds.\n")?,
+        );
+
+        let res = KeywordIndex::default()
+            .entries(&workspace, &BertModel::default())?;
+        let cargo = res.iter().find(|it| it.keyword == "cargo").unwrap();
+        assert_eq!(2, cargo.sections.len());
+        assert_eq!("cargo", res[0].keyword);
+
+        Ok(())
+    }
+}
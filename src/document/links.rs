@@ -0,0 +1,275 @@
+use std::ops::Range;
+use std::sync::OnceLock;
+
+use regex::Regex;
+use tower_lsp::lsp_types::{Location, Range as LspRange, Url};
+
+use super::document::{DocumentExt, SliceAccess, SyntaxTree};
+use super::document_adapter::{DocumentLsp, LspAdapter};
+use super::headings::{headings, Heading};
+use super::workspace::Workspace;
+
+fn inline_link() -> &'static Regex {
+    static REF: OnceLock<Regex> = OnceLock::new();
+    REF.get_or_init(|| {
+        Regex::new(r#"!?\[[^\]\n]*\]\(([^)\s]*)(?:\s+"[^"\n]*")?\)"#).unwrap()
+    })
+}
+
+pub(super) fn footnote_ref() -> &'static Regex {
+    static REF: OnceLock<Regex> = OnceLock::new();
+    REF.get_or_init(|| Regex::new(r#"\[\^([^\]\s]+)\]"#).unwrap())
+}
+
+/// A relative link to a markdown document, optionally with an `#anchor`.
+#[derive(Debug, PartialEq, Clone)]
+pub struct Link {
+    pub range: Range<usize>,
+    pub path: String,
+    pub anchor: Option<String>,
+}
+
+impl Link {
+    /// Resolve the link target against the uri of the containing document.
+    pub fn resolve(&self, base: &Url) -> Option<Url> {
+        if self.path.is_empty() {
+            return Some(base.clone());
+        }
+        base.join(&self.path).ok()
+    }
+}
+
+/// Collect inline links to local documents, skipping external urls and
+/// anything inside code blocks.
+pub fn links<D: SliceAccess + SyntaxTree>(doc: &D) -> Vec<Link> {
+    let text = doc.slice(0..);
+    inline_link()
+        .captures_iter(&text)
+        .filter(|cap| !cap[0].starts_with('!'))
+        .filter(|cap| !in_code_block(doc, cap.get(0).unwrap().start()))
+        .filter_map(|cap| {
+            let dest = &cap[1];
+            if dest.contains("://") || dest.starts_with("mailto:") {
+                return None;
+            }
+            let (path, anchor) = match dest.split_once('#') {
+                Some((path, anchor)) => (path, Some(anchor.to_string())),
+                None => (dest, None),
+            };
+            if path.is_empty() && anchor.is_none() {
+                return None;
+            }
+
+            Some(Link {
+                range: cap.get(0).unwrap().range(),
+                path: path.to_string(),
+                anchor,
+            })
+        })
+        .collect()
+}
+
+pub(super) fn in_code_block<D: SyntaxTree>(doc: &D, offset: usize) -> bool {
+    let mut node = doc
+        .tree()
+        .root_node()
+        .descendant_for_byte_range(offset, offset);
+    while let Some(it) = node {
+        if it.kind() == "fenced_code_block" ||
+            it.kind() == "indented_code_block"
+        {
+            return true;
+        }
+        node = it.parent();
+    }
+    false
+}
+
+/// GitHub-style heading anchor: lowercase, punctuation dropped, spaces
+/// replaced with dashes.
+pub fn slugify(title: &str) -> String {
+    title
+        .trim()
+        .chars()
+        .filter_map(|c| match c {
+            ' ' => Some('-'),
+            '-' | '_' => Some(c),
+            c if c.is_alphanumeric() => Some(c),
+            _ => None,
+        })
+        .flat_map(char::to_lowercase)
+        .collect()
+}
+
+/// The heading `#anchor` points at.
+pub fn find_heading<D: SliceAccess + SyntaxTree>(
+    doc: &D,
+    anchor: &str,
+) -> Option<Heading> {
+    let slug = slugify(anchor);
+    headings(doc)
+        .into_iter()
+        .find(|it| slugify(&doc.slice(it.title.clone())) == slug)
+}
+
+/// Location of the heading `#anchor` points at, or of the first heading
+/// without an anchor.
+pub fn heading_location<D: SliceAccess + SyntaxTree + LspAdapter>(
+    uri: &Url,
+    doc: &D,
+    anchor: Option<&str>,
+) -> Option<Location> {
+    let heading = match anchor {
+        Some(anchor) => find_heading(doc, anchor)?,
+        None => headings(doc).into_iter().next()?,
+    };
+    let start = doc.offset_to_position(heading.title.start)?;
+    let end = doc.offset_to_position(heading.title.end)?;
+    Some(Location::new(uri.clone(), LspRange::new(start, end)))
+}
+
+/// Links across the workspace pointing at the heading `#slug` in `uri`.
+pub fn backlinks(
+    workspace: &Workspace,
+    uri: &Url,
+    slug: &str,
+) -> Vec<Location> {
+    let mut ret = Vec::new();
+    for source in workspace.uris() {
+        let Some(doc) = workspace.get(&source) else {
+            continue;
+        };
+        for link in links(doc.value()) {
+            if link.resolve(&source).as_ref() != Some(uri) ||
+                link.anchor.as_deref().map(slugify).as_deref() != Some(slug)
+            {
+                continue;
+            }
+            let (Some(start), Some(end)) = (
+                doc.offset_to_position(link.range.start),
+                doc.offset_to_position(link.range.end),
+            ) else {
+                continue;
+            };
+            ret.push(Location::new(source.clone(), LspRange::new(start, end)));
+        }
+    }
+    ret
+}
+
+/// Links across the workspace pointing at the section whose heading is at
+/// `loc`.
+pub fn section_backlinks(
+    workspace: &Workspace,
+    loc: &Location,
+) -> anyhow::Result<Vec<Location>> {
+    let slug = {
+        let doc = workspace
+            .get(&loc.uri)
+            .ok_or_else(|| anyhow::anyhow!("unknown document: {}", loc.uri))?;
+        let section = doc
+            .position_to_section(&loc.range.start)
+            .ok_or_else(|| anyhow::anyhow!("no section at {:?}", loc.range))?;
+        slugify(&DocumentExt::title(doc.value(), section)?)
+    };
+    Ok(backlinks(workspace, &loc.uri, &slug))
+}
+
+/// Footnote label of a `[^label]` reference covering the offset.
+pub fn footnote_at<D: SliceAccess>(doc: &D, offset: usize) -> Option<String> {
+    let text = doc.slice(0..);
+    footnote_ref()
+        .captures_iter(&text)
+        .find(|cap| {
+            let m = cap.get(0).unwrap();
+            m.start() <= offset && offset < m.end()
+        })
+        .map(|cap| cap[1].to_string())
+}
+
+/// Text of the `[^label]: ...` definition, including indented continuation
+/// lines.
+pub fn footnote_definition<D: SliceAccess>(
+    doc: &D,
+    label: &str,
+) -> Option<String> {
+    let text = doc.slice(0..);
+    let marker = format!("[^{}]:", label);
+    let mut lines = text.lines().skip_while(|l| !l.starts_with(&marker));
+    let first = lines.next()?[marker.len()..].trim().to_string();
+
+    Some(
+        lines
+            .take_while(|l| l.starts_with(' ') || l.starts_with('\t'))
+            .fold(first, |mut acc, l| {
+                acc.push('\n');
+                acc.push_str(l.trim());
+                acc
+            }),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::Document;
+
+    const BUF: &str = r#"# Links
+
+See [other](other.md#Some-Section) and [local](#links), [web](https://example.com).
+
+```
+[code](ignored.md)
+```
+
+Footnote here[^note].
+
+[^note]: The footnote
+    spans lines.
+"#;
+
+    #[test]
+    fn links_should_skip_external_and_code() -> anyhow::Result<()> {
+        let doc = Document::parse(BUF)?;
+        let res = links(&doc);
+
+        assert_eq!(2, res.len());
+        assert_eq!("other.md", res[0].path);
+        assert_eq!(Some("Some-Section".to_string()), res[0].anchor);
+        assert_eq!(
+            "[other](other.md#Some-Section)",
+            &BUF[res[0].range.clone()]
+        );
+        assert_eq!("", res[1].path);
+
+        let base = Url::parse("file:///notes/dir/index.md")?;
+        assert_eq!(
+            Some(Url::parse("file:///notes/dir/other.md")?),
+            res[0].resolve(&base)
+        );
+        assert_eq!(Some(base.clone()), res[1].resolve(&base));
+
+        Ok(())
+    }
+
+    #[test]
+    fn slugify_should_follow_github_anchors() {
+        assert_eq!("some-section", slugify("Some Section"));
+        assert_eq!("whats-new-in-v2", slugify("What's new in v2?"));
+        assert_eq!("한글-제목", slugify("한글 제목"));
+    }
+
+    #[test]
+    fn footnotes_should_resolve() -> anyhow::Result<()> {
+        let doc = Document::parse(BUF)?;
+        let offset = BUF.find("[^note]").unwrap() + 2;
+
+        assert_eq!(Some("note".to_string()), footnote_at(&doc, offset));
+        assert_eq!(
+            Some("The footnote\nspans lines.".to_string()),
+            footnote_definition(&doc, "note")
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,205 @@
+use std::collections::HashSet;
+use std::ops::Range;
+
+use tower_lsp::lsp_types::{
+    Diagnostic, DiagnosticSeverity, NumberOrString, Range as LspRange, Url,
+};
+
+use super::document::SliceAccess;
+use super::document_adapter::LspAdapter;
+use super::headings::headings;
+use super::links::{
+    find_heading, footnote_definition, footnote_ref, in_code_block, links,
+};
+use super::tags::front_matter;
+use super::workspace::Workspace;
+use super::Document;
+
+/// Diagnostic source of lint findings.
+const SOURCE: &str = "lsp-md";
+
+/// Broken links and anchors, undefined footnotes, skipped heading levels and
+/// malformed front matter of a document. Diagnostics carry a rule name as
+/// their code.
+pub fn lint(workspace: &Workspace, uri: &Url) -> Vec<Diagnostic> {
+    let Some(doc) = workspace.get(uri) else {
+        return Vec::new();
+    };
+    let doc = doc.value();
+    let mut ret = Vec::new();
+    let mut push = |range: Range<usize>, severity, code: &str, message| {
+        let (Some(start), Some(end)) = (
+            doc.offset_to_position(range.start),
+            doc.offset_to_position(range.end),
+        ) else {
+            return;
+        };
+        ret.push(Diagnostic {
+            range: LspRange::new(start, end),
+            severity: Some(severity),
+            code: Some(NumberOrString::String(code.to_string())),
+            source: Some(SOURCE.to_string()),
+            message,
+            ..Default::default()
+        });
+    };
+
+    for link in links(doc) {
+        let Some(target) = link.resolve(uri) else {
+            continue;
+        };
+        let anchor = link.anchor.as_deref().filter(|it| !it.is_empty());
+        let found = match workspace.get(&target) {
+            Some(target_doc) => match anchor {
+                Some(it) => find_heading(target_doc.value(), it).is_some(),
+                None => true,
+            },
+            None if target.to_file_path().is_ok_and(|it| it.exists()) => true,
+            None => {
+                push(
+                    link.range,
+                    DiagnosticSeverity::ERROR,
+                    "broken-link",
+                    format!("{} does not exist", link.path),
+                );
+                continue;
+            },
+        };
+        if !found {
+            push(
+                link.range,
+                DiagnosticSeverity::ERROR,
+                "broken-anchor",
+                format!(
+                    "no heading #{} in {}",
+                    anchor.unwrap_or_default(),
+                    if link.path.is_empty() {
+                        "this document"
+                    } else {
+                        &link.path
+                    }
+                ),
+            );
+        }
+    }
+
+    let text = doc.slice(0..);
+    for cap in footnote_ref().captures_iter(&text) {
+        let m = cap.get(0).unwrap();
+        // Definitions are `[^label]:`.
+        if text[m.end()..].starts_with(':') || in_code_block(doc, m.start()) {
+            continue;
+        }
+        if footnote_definition(doc, &cap[1]).is_none() {
+            push(
+                m.range(),
+                DiagnosticSeverity::ERROR,
+                "undefined-footnote",
+                format!("footnote [^{}] is not defined", &cap[1]),
+            );
+        }
+    }
+
+    let mut level = 0;
+    for heading in headings(doc) {
+        if level > 0 && heading.level > level + 1 {
+            push(
+                heading.line.clone(),
+                DiagnosticSeverity::WARNING,
+                "heading-level",
+                format!(
+                    "heading level {} skips level {}",
+                    heading.level,
+                    level + 1
+                ),
+            );
+        }
+        level = heading.level;
+    }
+
+    for (range, message) in front_matter_errors(doc) {
+        push(range, DiagnosticSeverity::ERROR, "front-matter", message);
+    }
+
+    ret
+}
+
+/// Top level front matter lines that are not a `key: value` entry, and keys
+/// given twice.
+fn front_matter_errors(doc: &Document) -> Vec<(Range<usize>, String)> {
+    let Some(range) = front_matter(doc) else {
+        return Vec::new();
+    };
+    let text = doc.slice(range.clone());
+    let mut offset = range.start;
+    let mut keys = HashSet::new();
+    let mut ret = Vec::new();
+    for line in text.split_inclusive('\n') {
+        let start = offset;
+        offset += line.len();
+        let line = line.trim_end();
+        let nested = line.starts_with([' ', '\t', '-', '#']);
+        if line.is_empty() || line == "---" || nested {
+            continue;
+        }
+        let range = start..start + line.len();
+        match line.split_once(':') {
+            Some((key, _)) if !key.trim().is_empty() => {
+                if !keys.insert(key.trim()) {
+                    ret.push((
+                        range,
+                        format!("duplicate front matter key `{}`", key.trim()),
+                    ));
+                }
+            },
+            _ => ret.push((range, "expected `key: value`".to_string())),
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lint_should_report_broken_links_and_structure() -> anyhow::Result<()> {
+        let workspace = Workspace::default();
+        let a = Url::parse("file:///notes/a.md")?;
+        workspace.insert(
+            &a,
+            Document::parse(
+                "---\ntitle: A\ntitle: B\noops\n---\n\n# A\n\nSee \
+                 [b](b.md#setup), [c](b.md#missing), [d](d.md) and \
+                 [^1].\n\n### Deep\n",
+            )?,
+        );
+        workspace.insert(
+            &Url::parse("file:///notes/b.md")?,
+            Document::parse("# Setup\n")?,
+        );
+
+        let res: Vec<(u32, String)> = lint(&workspace, &a)
+            .into_iter()
+            .map(|it| {
+                let Some(NumberOrString::String(code)) = it.code else {
+                    panic!("expected a rule name");
+                };
+                (it.range.start.line, code)
+            })
+            .collect();
+        assert_eq!(
+            vec![
+                (8, "broken-anchor".to_string()),
+                (8, "broken-link".to_string()),
+                (8, "undefined-footnote".to_string()),
+                (10, "heading-level".to_string()),
+                (2, "front-matter".to_string()),
+                (3, "front-matter".to_string()),
+            ],
+            res
+        );
+
+        Ok(())
+    }
+}
@@ -1,20 +1,51 @@
 mod bert;
+mod bm25;
+mod chunks;
+mod clusters;
+mod code_lens;
 mod document;
 mod document_adapter;
 mod document_v2;
+mod duplicates;
 mod extract_keywords;
 mod find_by_keyword;
+mod folding_range;
 mod format;
+mod headings;
+mod hnsw;
+mod hover;
 mod incremental_sync;
 #[cfg(test)]
 mod integration_tests;
+mod keyword_index;
+mod links;
+mod lint;
+mod paragraphs;
 mod quick_edit;
+mod section_index;
 mod similar_notes;
+mod tags;
 mod test_doc;
+mod workspace;
+mod workspace_symbols;
 
-pub use bert::{BertModel, Encoder};
+pub use bert::{BertModel, Encoder, ModelType};
+pub use clusters::{clusters, ClusterOptions};
+pub use code_lens::{code_lenses, resolve_code_lens};
 pub use document_v2::Document;
+pub use duplicates::{duplicate_diagnostics, merge_action};
 pub use extract_keywords::extract_keywords;
 pub use find_by_keyword::find_by_keyword;
-pub use format::{Formatter as CodeFormatter, LspRangeFormat};
-pub use similar_notes::{find_similar, query_section_titles, ScoredLocation};
+pub use folding_range::folding_ranges;
+pub use format::{
+    Formatter as CodeFormatter, LspRangeFormat, DEFAULT_WRAP_WIDTH,
+};
+pub use hover::hover;
+pub use keyword_index::KeywordIndex;
+pub use links::{heading_location, section_backlinks};
+pub use lint::lint;
+pub use section_index::{SearchOptions, SectionIndex};
+pub use similar_notes::{find_similar, ScoredLocation};
+pub use tags::{resolve_tag_action, tag_action, TagEmbeddings};
+pub use workspace::Workspace;
+pub use workspace_symbols::workspace_symbols;
@@ -0,0 +1,51 @@
+use std::ops::Range;
+
+use super::document::SyntaxTree;
+use super::format::Traversal;
+
+/// Byte ranges of the paragraphs and list items of a document, in order.
+/// Items of nested lists belong to their top-level item.
+pub fn paragraphs<D: SyntaxTree>(doc: &D) -> Vec<Range<usize>> {
+    let mut ret = Vec::new();
+    for node in Traversal::from_cursor(doc.tree().walk()) {
+        if node.kind() == "paragraph" {
+            // Setext heading titles parse as paragraphs.
+            if node
+                .parent()
+                .is_some_and(|it| it.kind() != "setext_heading")
+            {
+                ret.push(node.byte_range());
+            }
+            continue;
+        }
+        let mut cursor = node.walk();
+        for item in node.named_children(&mut cursor) {
+            if item.kind() == "list_item" {
+                ret.push(item.byte_range());
+            }
+        }
+    }
+    ret
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::Document;
+
+    #[test]
+    fn paragraphs_should_include_list_items() -> anyhow::Result<()> {
+        let text = "Title\n===\n\nFirst paragraph.\n\n- one\n  - nested\n- \
+                    two\n\n```\ncode\n```\n";
+        let doc = Document::parse(text)?;
+
+        let res: Vec<&str> =
+            paragraphs(&doc).into_iter().map(|it| &text[it]).collect();
+        assert_eq!(3, res.len());
+        assert_eq!("First paragraph.\n", res[0]);
+        assert!(res[1].starts_with("- one") && res[1].contains("nested"));
+        assert!(res[2].starts_with("- two"));
+
+        Ok(())
+    }
+}
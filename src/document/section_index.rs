@@ -0,0 +1,1256 @@
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+use dashmap::mapref::entry::Entry;
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use tower_lsp::lsp_types::{Location, Range, Url};
+
+use super::bert::{Embedding, ModelType, QuantizedEmbedding, DIMENSIONS};
+use super::bm25::{Bm25, SectionKey};
+use super::chunks::chunks;
+use super::document::{BasicDocument, DocumentExt, SliceAccess};
+use super::document_adapter::{DocumentLsp, LspAdapter};
+use super::hnsw::Hnsw;
+use super::paragraphs::paragraphs;
+use super::workspace::Workspace;
+use super::{Document, Encoder, ScoredLocation};
+
+type UriFilter<'a> = Box<dyn Fn(&Url) -> bool + 'a>;
+
+/// A result with the embedding it was scored by.
+type Scored = (ScoredLocation<'static>, Embedding);
+
+/// Rank offset of reciprocal rank fusion; damps the weight of top ranks.
+const RRF_K: f32 = 60.0;
+
+/// Below this many sections `rank` scans linearly unless `ef` is given.
+const ANN_MIN_SECTIONS: usize = 1000;
+const DEFAULT_EF: usize = 64;
+/// Approximate hits fetched per requested result, leaving room for hits
+/// outside the search scope.
+const ANN_OVERSAMPLE: usize = 4;
+/// Candidates per requested result kept from the int8 first pass for float
+/// re-scoring.
+const RESCORE_FACTOR: usize = 4;
+
+/// Which documents a search looks at, relative to the requesting document.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Scope {
+    File,
+    Folder,
+    #[default]
+    Workspace,
+    Glob(String),
+}
+
+/// Unit of similarity search.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Granularity {
+    #[default]
+    Section,
+    /// Paragraphs and list items.
+    Paragraph,
+}
+
+/// How the chunk similarities of a long section combine into its score.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum Pooling {
+    /// Similarity to the mean of the chunk embeddings.
+    #[default]
+    Mean,
+    /// Similarity of the best matching chunk.
+    Max,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchOptions {
+    pub limit: Option<usize>,
+    pub min_score: Option<f32>,
+    #[serde(default)]
+    pub scope: Scope,
+    /// Weights of the embedding and BM25 rankings in hybrid search, 1.0 each
+    /// unless given.
+    pub vector_weight: Option<f32>,
+    pub lexical_weight: Option<f32>,
+    /// Beam width of approximate search; larger is slower but finds more of
+    /// the true nearest sections. Setting it forces approximate search.
+    pub ef: Option<usize>,
+    #[serde(default)]
+    pub pooling: Pooling,
+    #[serde(default)]
+    pub granularity: Granularity,
+    /// Diversify results by maximal marginal relevance: 1.0 orders by score
+    /// alone, lower values penalize results similar to those ranked above.
+    pub mmr_lambda: Option<f32>,
+}
+
+impl Scope {
+    fn filter<'a>(
+        &'a self,
+        origin: &Url,
+        roots: &'a [PathBuf],
+    ) -> anyhow::Result<UriFilter<'a>> {
+        let origin = origin.clone();
+        Ok(match self {
+            Scope::File => Box::new(move |uri| uri == &origin),
+            Scope::Folder => {
+                let folder = origin.join(".")?;
+                Box::new(move |uri| uri.as_str().starts_with(folder.as_str()))
+            },
+            Scope::Workspace => Box::new(|_| true),
+            Scope::Glob(pattern) => {
+                let pattern = glob::Pattern::new(pattern)?;
+                Box::new(move |uri| {
+                    let Ok(path) = uri.to_file_path() else {
+                        return false;
+                    };
+                    pattern.matches_path(&path) ||
+                        roots.iter().any(|root| {
+                            path.strip_prefix(root)
+                                .is_ok_and(|it| pattern.matches_path(it))
+                        })
+                })
+            },
+        })
+    }
+}
+
+/// Embedding of a section. Sections longer than the encoder input are
+/// embedded in chunks, and the section embedding is their mean.
+#[derive(Clone)]
+struct SectionEmbedding {
+    hash: u64,
+    embedding: Embedding,
+    quantized: QuantizedEmbedding,
+    chunks: Vec<ChunkEmbedding>,
+}
+
+/// Byte range relative to the section start, so cached chunks stay valid
+/// when the section moves.
+#[derive(Clone)]
+struct ChunkEmbedding {
+    range: std::ops::Range<usize>,
+    embedding: Embedding,
+}
+
+impl SectionEmbedding {
+    fn new(
+        hash: u64,
+        embedding: Embedding,
+        chunks: Vec<ChunkEmbedding>,
+    ) -> Self {
+        let quantized = QuantizedEmbedding::new(&embedding);
+        SectionEmbedding {
+            hash,
+            embedding,
+            quantized,
+            chunks,
+        }
+    }
+
+    fn from_chunks(hash: u64, chunks: Vec<ChunkEmbedding>) -> Self {
+        let mut sum = vec![0.0; DIMENSIONS];
+        for chunk in &chunks {
+            for (acc, it) in sum.iter_mut().zip(chunk.embedding.values()) {
+                *acc += it;
+            }
+        }
+        Self::new(hash, Embedding::new(sum), chunks)
+    }
+}
+
+/// A section embedding as stored on disk. Vectors are kept as floats, so
+/// scores do not change across restarts; the int8 form is derived on load.
+#[derive(Serialize, Deserialize)]
+struct StoredSection {
+    hash: u64,
+    embedding: Embedding,
+    #[serde(default)]
+    chunks: Vec<StoredChunk>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredChunk {
+    start: usize,
+    end: usize,
+    embedding: Embedding,
+}
+
+/// A section in search scope.
+struct Candidate {
+    uri: Url,
+    index: usize,
+    title: String,
+    range: Range,
+    embedding: Embedding,
+    quantized: QuantizedEmbedding,
+    chunks: Vec<(Range, Embedding)>,
+}
+
+impl Candidate {
+    fn new(
+        uri: &Url,
+        doc: &Document,
+        index: usize,
+        section: SectionEmbedding,
+    ) -> anyhow::Result<Option<Self>> {
+        let Some(range) = doc.section_to_title_range(index) else {
+            return Ok(None);
+        };
+        let start = doc.sections()[index].range.start;
+        let chunks = section
+            .chunks
+            .into_iter()
+            .filter_map(|it| {
+                let range = Range::new(
+                    doc.offset_to_position(start + it.range.start)?,
+                    doc.offset_to_position(start + it.range.end)?,
+                );
+                Some((range, it.embedding))
+            })
+            .collect();
+
+        Ok(Some(Candidate {
+            uri: uri.clone(),
+            index,
+            title: DocumentExt::title(doc, index)?.into_owned(),
+            range,
+            embedding: section.embedding,
+            quantized: section.quantized,
+            chunks,
+        }))
+    }
+
+    /// Similarity to the query under the pooling mode, with the best matching
+    /// chunk if the section was chunked.
+    fn score(
+        &self,
+        query: &Embedding,
+        pooling: Pooling,
+    ) -> (f32, Option<Range>) {
+        let best = self
+            .chunks
+            .iter()
+            .map(|(range, it)| (it.cos(query), *range))
+            .max_by(|a, b| a.0.total_cmp(&b.0));
+        match (pooling, best) {
+            (Pooling::Max, Some((score, range))) => (score, Some(range)),
+            (_, best) => (self.embedding.cos(query), best.map(|it| it.1)),
+        }
+    }
+
+    fn is(&self, key: &Option<(Url, usize)>) -> bool {
+        matches!(key, Some((uri, index)) if *uri == self.uri && *index == self.index)
+    }
+
+    fn into_scored(self, score: f32, chunk: Option<Range>) -> Scored {
+        let location = ScoredLocation {
+            score,
+            title: Cow::Owned(self.title),
+            location: Location::new(self.uri, self.range),
+            vector_score: None,
+            lexical_score: None,
+            chunk,
+        };
+        (location, self.embedding)
+    }
+}
+
+/// On-disk form of the index. The lexical index is cheap to rebuild and is
+/// left out.
+#[derive(Serialize, Deserialize)]
+struct Snapshot<A> {
+    documents: HashMap<String, Vec<StoredSection>>,
+    ann: A,
+}
+
+/// Embeddings of every section in the workspace, keyed by document uri, with
+/// a lexical index and an approximate nearest neighbour graph over the same
+/// sections. Sections are only re-encoded when their content changes.
+#[derive(Default)]
+pub struct SectionIndex {
+    documents: DashMap<String, Vec<SectionEmbedding>>,
+    lexical: Mutex<Bm25>,
+    ann: Mutex<Hnsw<SectionKey>>,
+    /// Paragraph embeddings of each document by content hash.
+    paragraphs: DashMap<String, HashMap<u64, Embedding>>,
+}
+
+impl SectionIndex {
+    /// Embeddings for each section of the document, in section order.
+    pub fn embed(
+        &self,
+        uri: &Url,
+        doc: &Document,
+        enc: &impl Encoder,
+    ) -> anyhow::Result<Vec<Embedding>> {
+        Ok(self
+            .sections(uri, doc, enc)?
+            .into_iter()
+            .map(|it| it.embedding)
+            .collect())
+    }
+
+    /// Forget a document and every embedding of it.
+    pub fn remove(&self, uri: &Url) {
+        if let Some((_, sections)) = self.documents.remove(uri.as_str()) {
+            let mut ann = self.ann.lock().unwrap();
+            for i in 0..sections.len() {
+                ann.remove(&(uri.to_string(), i));
+            }
+        }
+        self.lexical.lock().unwrap().remove(uri.as_str());
+        self.paragraphs.remove(uri.as_str());
+    }
+
+    fn sections(
+        &self,
+        uri: &Url,
+        doc: &Document,
+        enc: &impl Encoder,
+    ) -> anyhow::Result<Vec<SectionEmbedding>> {
+        let texts: Vec<String> = (0..doc.sections().len())
+            .map(|i| Ok(DocumentExt::text(doc, i)?.into_owned()))
+            .collect::<anyhow::Result<_>>()?;
+        let hashes: Vec<u64> = texts.iter().map(|it| hash(it)).collect();
+
+        let previous = self
+            .documents
+            .remove(uri.as_str())
+            .map(|(_, v)| v)
+            .unwrap_or_default();
+        let changed = previous.len() != hashes.len() ||
+            previous.iter().zip(&hashes).any(|(a, b)| a.hash != *b);
+        let mut lexical = self.lexical.lock().unwrap();
+        if changed || !lexical.contains(uri.as_str()) {
+            lexical.insert(uri.as_str(), &texts);
+        }
+        drop(lexical);
+        let stale: Vec<usize> = (0..hashes.len())
+            .filter(|&i| previous.get(i).map(|it| it.hash) != Some(hashes[i]))
+            .collect();
+        let removed = hashes.len()..previous.len();
+
+        let mut cached: HashMap<u64, SectionEmbedding> =
+            previous.into_iter().map(|it| (it.hash, it)).collect();
+        let missing: Vec<usize> = (0..texts.len())
+            .filter(|&i| !cached.contains_key(&hashes[i]))
+            .collect();
+        if !missing.is_empty() {
+            let sections = doc.sections();
+            let mut batch: Vec<String> = Vec::new();
+            let mut pieces = Vec::new();
+            for &i in &missing {
+                let range = sections[i].range.clone();
+                let chunks = chunks(doc, range.clone());
+                if chunks.len() > 1 {
+                    for it in &chunks {
+                        batch.push(doc.slice(it.clone()).into_owned());
+                    }
+                    pieces.push((i, range.start, chunks));
+                } else {
+                    batch.push(texts[i].clone());
+                    pieces.push((i, range.start, Vec::new()));
+                }
+            }
+
+            let mut embeddings = enc.encode_batch(&batch)?.into_iter();
+            for (i, start, chunks) in pieces {
+                let section = if chunks.is_empty() {
+                    let Some(embedding) = embeddings.next() else {
+                        anyhow::bail!("encoder returned too few embeddings");
+                    };
+                    SectionEmbedding::new(hashes[i], embedding, Vec::new())
+                } else {
+                    let chunks = chunks
+                        .into_iter()
+                        .zip(embeddings.by_ref())
+                        .map(|(range, embedding)| ChunkEmbedding {
+                            range: range.start - start..range.end - start,
+                            embedding,
+                        })
+                        .collect();
+                    SectionEmbedding::from_chunks(hashes[i], chunks)
+                };
+                cached.insert(hashes[i], section);
+            }
+        }
+
+        let sections: Vec<SectionEmbedding> =
+            hashes.iter().map(|hash| cached[hash].clone()).collect();
+        let mut ann = self.ann.lock().unwrap();
+        for i in removed {
+            ann.remove(&(uri.to_string(), i));
+        }
+        for i in stale {
+            ann.insert((uri.to_string(), i), sections[i].embedding.clone());
+        }
+        drop(ann);
+
+        self.documents.insert(uri.to_string(), sections.clone());
+        Ok(sections)
+    }
+
+    /// Embeddings of each paragraph and list item of the document with their
+    /// byte ranges, cached by content like sections.
+    pub fn embed_paragraphs(
+        &self,
+        uri: &Url,
+        doc: &Document,
+        enc: &impl Encoder,
+    ) -> anyhow::Result<Vec<(std::ops::Range<usize>, Embedding)>> {
+        let ranges = paragraphs(doc);
+        let hashes: Vec<u64> = ranges
+            .iter()
+            .map(|it| hash(&doc.slice(it.clone())))
+            .collect();
+
+        let mut cached = self
+            .paragraphs
+            .remove(uri.as_str())
+            .map(|(_, v)| v)
+            .unwrap_or_default();
+        let missing: Vec<usize> = (0..ranges.len())
+            .filter(|&i| !cached.contains_key(&hashes[i]))
+            .collect();
+        if !missing.is_empty() {
+            let batch: Vec<String> = missing
+                .iter()
+                .map(|&i| doc.slice(ranges[i].clone()).into_owned())
+                .collect();
+            for (&i, embedding) in missing.iter().zip(enc.encode_batch(&batch)?)
+            {
+                cached.insert(hashes[i], embedding);
+            }
+        }
+
+        let ret = ranges
+            .into_iter()
+            .zip(&hashes)
+            .map(|(range, hash)| (range, cached[hash].clone()))
+            .collect();
+        let current: HashSet<u64> = hashes.into_iter().collect();
+        cached.retain(|it, _| current.contains(it));
+        self.paragraphs.insert(uri.to_string(), cached);
+
+        Ok(ret)
+    }
+
+    /// Cache file for the workspace roots and model, under
+    /// `$XDG_CACHE_HOME/lsp-md` or `~/.cache/lsp-md`.
+    pub fn cache_path(roots: &[PathBuf], model: ModelType) -> Option<PathBuf> {
+        if roots.is_empty() {
+            return None;
+        }
+        let base = std::env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| {
+                std::env::var_os("HOME")
+                    .map(|it| PathBuf::from(it).join(".cache"))
+            })?;
+        let mut hasher = StableHasher::default();
+        roots.hash(&mut hasher);
+        model.hash(&mut hasher);
+        Some(
+            base.join("lsp-md")
+                .join(format!("{:016x}.cbor", hasher.finish())),
+        )
+    }
+
+    /// Write embeddings and the neighbour graph of the documents matching
+    /// `keep` to a cache file.
+    pub fn save(
+        &self,
+        path: &Path,
+        keep: impl Fn(&Url) -> bool,
+    ) -> anyhow::Result<()> {
+        let keep = |uri: &str| Url::parse(uri).is_ok_and(|it| keep(&it));
+        let documents = self
+            .documents
+            .iter()
+            .filter(|it| keep(it.key()))
+            .map(|it| {
+                let sections = it
+                    .value()
+                    .iter()
+                    .map(|it| StoredSection {
+                        hash: it.hash,
+                        embedding: it.embedding.clone(),
+                        chunks: it
+                            .chunks
+                            .iter()
+                            .map(|it| StoredChunk {
+                                start: it.range.start,
+                                end: it.range.end,
+                                embedding: it.embedding.clone(),
+                            })
+                            .collect(),
+                    })
+                    .collect();
+                (it.key().clone(), sections)
+            })
+            .collect();
+        let ann = self.ann.lock().unwrap().retained(|(uri, _)| keep(uri));
+        let mut buf = Vec::new();
+        ciborium::into_writer(&Snapshot { documents, ann }, &mut buf)?;
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, buf)?;
+        fs::rename(tmp, path)?;
+        Ok(())
+    }
+
+    /// Merge a cache file written by `save` into the index. Documents
+    /// already in the index are kept, the cache only fills in the others.
+    /// Documents are checked against their section hashes on next use, so a
+    /// stale cache only costs re-encoding.
+    pub fn load(&self, path: &Path) -> anyhow::Result<()> {
+        let snapshot: Snapshot<Hnsw<SectionKey>> =
+            ciborium::from_reader(fs::File::open(path)?)?;
+        let documents: HashMap<String, Vec<SectionEmbedding>> = snapshot
+            .documents
+            .into_iter()
+            .map(|(uri, sections)| {
+                let sections = sections
+                    .into_iter()
+                    .map(|it| {
+                        let chunks = it
+                            .chunks
+                            .into_iter()
+                            .map(|it| ChunkEmbedding {
+                                range: it.start..it.end,
+                                embedding: it.embedding,
+                            })
+                            .collect();
+                        SectionEmbedding::new(it.hash, it.embedding, chunks)
+                    })
+                    .collect();
+                (uri, sections)
+            })
+            .collect();
+        // The stored graph is only reused when nothing was indexed yet,
+        // otherwise the cached sections are inserted one by one.
+        let mut ann = self.ann.lock().unwrap();
+        let mut cached = snapshot.ann;
+        let restored = ann.len() == 0 &&
+            cached.restore(|(uri, i)| {
+                Some(documents.get(uri)?.get(*i)?.embedding.clone())
+            });
+        if restored {
+            *ann = cached;
+        }
+        for (uri, sections) in documents {
+            match self.documents.entry(uri) {
+                Entry::Occupied(entry) if restored => {
+                    for i in 0..sections.len() {
+                        ann.remove(&(entry.key().clone(), i));
+                    }
+                    for (i, it) in entry.get().iter().enumerate() {
+                        ann.insert(
+                            (entry.key().clone(), i),
+                            it.embedding.clone(),
+                        );
+                    }
+                },
+                Entry::Occupied(_) => {},
+                Entry::Vacant(entry) => {
+                    if !restored {
+                        for (i, it) in sections.iter().enumerate() {
+                            ann.insert(
+                                (entry.key().clone(), i),
+                                it.embedding.clone(),
+                            );
+                        }
+                    }
+                    entry.insert(sections);
+                },
+            }
+        }
+        Ok(())
+    }
+
+    /// Rank sections in scope by cosine similarity to the query embedding,
+    /// leaving out the section at `exclude`. Large indexes, or an explicit
+    /// `ef`, use the approximate neighbour graph when a limit is set and
+    /// pooling is by mean, falling back to a linear scan if too few
+    /// approximate hits are in scope. The linear scan ranks by int8 dot
+    /// product, or best chunk under max pooling, first and re-scores the best
+    /// candidates with floats.
+    pub fn rank(
+        &self,
+        workspace: &Workspace,
+        enc: &impl Encoder,
+        origin: &Url,
+        query: &Embedding,
+        exclude: Option<&Location>,
+        opts: &SearchOptions,
+    ) -> anyhow::Result<Vec<ScoredLocation<'static>>> {
+        let excluded = exclude.and_then(|loc| {
+            let doc = workspace.get(&loc.uri)?;
+            Some((loc.uri.clone(), doc.position_to_section(&loc.range.start)?))
+        });
+        let score = |candidates: Vec<Candidate>| -> Vec<Scored> {
+            candidates
+                .into_iter()
+                .map(|it| {
+                    let (score, chunk) = it.score(query, opts.pooling);
+                    it.into_scored(score, chunk)
+                })
+                .collect()
+        };
+
+        // The graph holds section means, which can miss a section whose best
+        // chunk matches under max pooling.
+        if let (Some(limit), Pooling::Mean) = (opts.limit, opts.pooling) {
+            let large = self.ann.lock().unwrap().len() >= ANN_MIN_SECTIONS;
+            if large || opts.ef.is_some() {
+                let mut candidates = self
+                    .approximate_candidates(workspace, origin, query, opts)?;
+                candidates.retain(|it| !it.is(&excluded));
+                if candidates.len() >= limit {
+                    return Ok(select(score(candidates), opts));
+                }
+            }
+        }
+
+        let mut candidates = self.candidates(workspace, enc, origin, opts)?;
+        candidates.retain(|it| !it.is(&excluded));
+        if let Some(limit) = opts.limit {
+            let keep = limit * RESCORE_FACTOR;
+            if candidates.len() > keep {
+                let quantized = QuantizedEmbedding::new(query);
+                let mut scored: Vec<(f32, Candidate)> = candidates
+                    .into_iter()
+                    .map(|it| {
+                        // The best chunk decides under max pooling, chunks
+                        // are few enough to score with floats.
+                        let first = match opts.pooling {
+                            Pooling::Max if !it.chunks.is_empty() => {
+                                it.score(query, Pooling::Max).0
+                            },
+                            _ => it.quantized.dot(&quantized),
+                        };
+                        (first, it)
+                    })
+                    .collect();
+                scored.select_nth_unstable_by(keep, |a, b| b.0.total_cmp(&a.0));
+                scored.truncate(keep);
+                candidates = scored.into_iter().map(|it| it.1).collect();
+            }
+        }
+
+        Ok(select(score(candidates), opts))
+    }
+
+    /// Rank paragraphs and list items in scope by cosine similarity to the
+    /// query embedding, leaving out the paragraph at `exclude`. Results point
+    /// at the paragraph itself and are titled by the enclosing section.
+    pub fn rank_paragraphs(
+        &self,
+        workspace: &Workspace,
+        enc: &impl Encoder,
+        origin: &Url,
+        query: &Embedding,
+        exclude: Option<&Location>,
+        opts: &SearchOptions,
+    ) -> anyhow::Result<Vec<ScoredLocation<'static>>> {
+        let roots = workspace.roots();
+        let in_scope = opts.scope.filter(origin, &roots)?;
+        let excluded = exclude.and_then(|loc| {
+            let doc = workspace.get(&loc.uri)?;
+            Some((loc.uri.clone(), doc.position_to_offset(&loc.range.start)?))
+        });
+
+        let mut ret = Vec::new();
+        for uri in workspace.uris().into_iter().filter(|it| in_scope(it)) {
+            let Some(doc) = workspace.get(&uri) else {
+                continue;
+            };
+            let doc = doc.value();
+            let sections = doc.sections();
+            for (range, embedding) in self.embed_paragraphs(&uri, doc, enc)? {
+                if matches!(&excluded, Some((it, offset)) if *it == uri && range.contains(offset))
+                {
+                    continue;
+                }
+                let end =
+                    range.start + doc.slice(range.clone()).trim_end().len();
+                let (Some(start), Some(end)) = (
+                    doc.offset_to_position(range.start),
+                    doc.offset_to_position(end),
+                ) else {
+                    continue;
+                };
+                let title = match sections
+                    .iter()
+                    .position(|it| it.range.contains(&range.start))
+                {
+                    Some(i) => DocumentExt::title(doc, i)?.into_owned(),
+                    None => String::new(),
+                };
+                let location = ScoredLocation {
+                    score: embedding.cos(query),
+                    title: Cow::Owned(title),
+                    location: Location::new(
+                        uri.clone(),
+                        Range::new(start, end),
+                    ),
+                    vector_score: None,
+                    lexical_score: None,
+                    chunk: None,
+                };
+                ret.push((location, embedding));
+            }
+        }
+
+        Ok(select(ret, opts))
+    }
+
+    /// Rank sections by fusing the embedding ranking with the BM25 ranking of
+    /// the query text (weighted reciprocal rank fusion). Fused scores are
+    /// normalized so a section ranked first by both gets 1.0.
+    pub fn rank_hybrid(
+        &self,
+        workspace: &Workspace,
+        enc: &impl Encoder,
+        origin: &Url,
+        text: &str,
+        query: &Embedding,
+        opts: &SearchOptions,
+    ) -> anyhow::Result<Vec<ScoredLocation<'static>>> {
+        let candidates = self.candidates(workspace, enc, origin, opts)?;
+        let lexical = self.lexical.lock().unwrap().search(text);
+
+        let (vector_scores, chunks): (Vec<f32>, Vec<Option<Range>>) =
+            candidates
+                .iter()
+                .map(|it| it.score(query, opts.pooling))
+                .unzip();
+        let lexical_scores: Vec<f32> = candidates
+            .iter()
+            .map(|it| {
+                let key = (it.uri.to_string(), it.index);
+                lexical.get(&key).copied().unwrap_or_default()
+            })
+            .collect();
+        let vector_ranks = ranks(&vector_scores);
+        let lexical_ranks = ranks(&lexical_scores);
+
+        let vector_weight = opts.vector_weight.unwrap_or(1.0);
+        let lexical_weight = opts.lexical_weight.unwrap_or(1.0);
+        let norm = (vector_weight + lexical_weight) / (RRF_K + 1.0);
+
+        let ret = candidates
+            .into_iter()
+            .enumerate()
+            .map(|(i, it)| {
+                let mut fused = vector_weight / (RRF_K + vector_ranks[i]);
+                if lexical_scores[i] > 0.0 {
+                    fused += lexical_weight / (RRF_K + lexical_ranks[i]);
+                }
+                let mut ret = it.into_scored(fused / norm, chunks[i]);
+                ret.0.vector_score = Some(vector_scores[i]);
+                ret.0.lexical_score = Some(lexical_scores[i]);
+                ret
+            })
+            .collect();
+
+        Ok(select(ret, opts))
+    }
+
+    /// Sections in scope among the approximate nearest neighbours of the
+    /// query. Only sections embedded already are searched, callers keep the
+    /// index current with `embed`; hits whose section has changed since are
+    /// skipped.
+    fn approximate_candidates(
+        &self,
+        workspace: &Workspace,
+        origin: &Url,
+        query: &Embedding,
+        opts: &SearchOptions,
+    ) -> anyhow::Result<Vec<Candidate>> {
+        let limit = opts.limit.unwrap_or_default();
+        let roots = workspace.roots();
+        let in_scope = opts.scope.filter(origin, &roots)?;
+
+        let ef = opts.ef.unwrap_or(DEFAULT_EF);
+        let hits =
+            self.ann
+                .lock()
+                .unwrap()
+                .search(query, limit * ANN_OVERSAMPLE, ef);
+
+        let mut ret = Vec::new();
+        for ((uri, index), _) in hits {
+            let uri = Url::parse(&uri)?;
+            if !in_scope(&uri) {
+                continue;
+            }
+            let (Some(doc), Some(section)) = (
+                workspace.get(&uri),
+                self.documents
+                    .get(uri.as_str())
+                    .and_then(|it| it.get(index).cloned()),
+            ) else {
+                continue;
+            };
+            let doc = doc.value();
+            if index >= doc.sections().len() ||
+                hash(&DocumentExt::text(doc, index)?) != section.hash
+            {
+                continue;
+            }
+            ret.extend(Candidate::new(&uri, doc, index, section)?);
+        }
+        Ok(ret)
+    }
+
+    fn candidates(
+        &self,
+        workspace: &Workspace,
+        enc: &impl Encoder,
+        origin: &Url,
+        opts: &SearchOptions,
+    ) -> anyhow::Result<Vec<Candidate>> {
+        let roots = workspace.roots();
+        let in_scope = opts.scope.filter(origin, &roots)?;
+
+        let mut ret = Vec::new();
+        for uri in workspace.uris().into_iter().filter(|it| in_scope(it)) {
+            let Some(doc) = workspace.get(&uri) else {
+                continue;
+            };
+            let doc = doc.value();
+            for (index, section) in
+                self.sections(&uri, doc, enc)?.into_iter().enumerate()
+            {
+                ret.extend(Candidate::new(&uri, doc, index, section)?);
+            }
+        }
+        Ok(ret)
+    }
+}
+
+/// Apply the score threshold, order by score, or by maximal marginal
+/// relevance when a lambda is given, and truncate.
+fn select(
+    mut ret: Vec<Scored>,
+    opts: &SearchOptions,
+) -> Vec<ScoredLocation<'static>> {
+    if let Some(min) = opts.min_score {
+        ret.retain(|it| it.0.score >= min);
+    }
+    ret.sort_by(|a, b| b.0.score.total_cmp(&a.0.score));
+    let limit = opts.limit.unwrap_or(ret.len());
+
+    let Some(lambda) = opts.mmr_lambda else {
+        ret.truncate(limit);
+        return ret.into_iter().map(|it| it.0).collect();
+    };
+    if opts.limit.is_some() {
+        ret.truncate(limit * RESCORE_FACTOR);
+    }
+    mmr(ret, lambda.clamp(0.0, 1.0), limit)
+}
+
+/// Greedily pick the result with the best trade-off between its score and
+/// its similarity to the results picked before it.
+fn mmr(
+    mut pool: Vec<Scored>,
+    lambda: f32,
+    limit: usize,
+) -> Vec<ScoredLocation<'static>> {
+    let mut picked: Vec<Scored> = Vec::new();
+    while picked.len() < limit && !pool.is_empty() {
+        let best = pool
+            .iter()
+            .enumerate()
+            .map(|(i, (loc, embedding))| {
+                let redundancy = picked
+                    .iter()
+                    .map(|it| it.1.cos(embedding))
+                    .reduce(f32::max)
+                    .unwrap_or_default();
+                (lambda * loc.score - (1.0 - lambda) * redundancy, i)
+            })
+            .max_by(|a, b| a.0.total_cmp(&b.0).then(b.1.cmp(&a.1)))
+            .map_or(0, |it| it.1);
+        picked.push(pool.remove(best));
+    }
+    picked.into_iter().map(|it| it.0).collect()
+}
+
+/// 1-based rank of each score in descending order.
+fn ranks(scores: &[f32]) -> Vec<f32> {
+    let mut order: Vec<usize> = (0..scores.len()).collect();
+    order.sort_by(|&a, &b| scores[b].partial_cmp(&scores[a]).unwrap());
+    let mut ret = vec![0.0; scores.len()];
+    for (rank, i) in order.into_iter().enumerate() {
+        ret[i] = (rank + 1) as f32;
+    }
+    ret
+}
+
+pub(super) fn hash(text: &str) -> u64 {
+    let mut hasher = StableHasher::default();
+    text.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// FNV-1a. Hashes are persisted in the cache and name its file, so unlike
+/// `DefaultHasher` they must not change between builds.
+struct StableHasher(u64);
+
+impl Default for StableHasher {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Hasher for StableHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for it in bytes {
+            self.0 ^= u64::from(*it);
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+    use crate::document::BertModel;
+
+    #[test]
+    fn hash_should_be_stable() {
+        let mut hasher = StableHasher::default();
+        hasher.write(b"a");
+        assert_eq!(0xaf63_dc4c_8601_ec8c, hasher.finish());
+    }
+
+    #[test]
+    fn search_options_should_parse() -> anyhow::Result<()> {
+        let opts: SearchOptions = serde_json::from_value(json!({
+            "limit": 5,
+            "minScore": 0.5,
+            "scope": { "glob": "daily/*.md" },
+        }))?;
+        assert_eq!(Some(5), opts.limit);
+        assert_eq!(Some(0.5), opts.min_score);
+        assert_eq!(Scope::Glob("daily/*.md".to_string()), opts.scope);
+
+        let opts: SearchOptions = serde_json::from_value(json!({
+            "scope": "folder",
+        }))?;
+        assert_eq!(Scope::Folder, opts.scope);
+        assert_eq!(
+            Scope::Workspace,
+            serde_json::from_value::<SearchOptions>(json!({}))?.scope
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn scope_filter_should_match_documents() -> anyhow::Result<()> {
+        let origin = Url::parse("file:///notes/daily/today.md")?;
+        let other = Url::parse("file:///notes/daily/yesterday.md")?;
+        let outside = Url::parse("file:///notes/ideas.md")?;
+        let roots = vec![PathBuf::from("/notes")];
+
+        let file = Scope::File.filter(&origin, &roots)?;
+        assert!(file(&origin) && !file(&other));
+
+        let folder = Scope::Folder.filter(&origin, &roots)?;
+        assert!(folder(&other) && !folder(&outside));
+
+        let glob = Scope::Glob("daily/*.md".to_string());
+        let glob = glob.filter(&origin, &roots)?;
+        assert!(glob(&other) && !glob(&outside));
+
+        Ok(())
+    }
+
+    #[test]
+    fn rank_should_search_workspace() -> anyhow::Result<()> {
+        let workspace = Workspace::default();
+        workspace.set_roots(vec![PathBuf::from("/notes")]);
+        let a = Url::parse("file:///notes/a.md")?;
+        let b = Url::parse("file:///notes/b.md")?;
+        workspace.insert(&a, Document::parse("# Rust\n\nBorrow checker.\n")?);
+        workspace.insert(&b, Document::parse("# Cooking\n\nPasta recipe.\n")?);
+        let model = BertModel::default();
+        let index = SectionIndex::default();
+        let query = model.encode("borrow checker in rust")?;
+
+        let res = index.rank(
+            &workspace,
+            &model,
+            &a,
+            &query,
+            None,
+            &SearchOptions::default(),
+        )?;
+        assert_eq!(2, res.len());
+        assert_eq!(a, res[0].location.uri);
+
+        let opts = SearchOptions {
+            limit: Some(1),
+            scope: Scope::Glob("b.md".to_string()),
+            ..Default::default()
+        };
+        let res = index.rank(&workspace, &model, &a, &query, None, &opts)?;
+        assert_eq!(
+            vec![b],
+            res.iter()
+                .map(|it| it.location.uri.clone())
+                .collect::<Vec<_>>()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn remove_should_forget_document() -> anyhow::Result<()> {
+        let a = Url::parse("file:///notes/a.md")?;
+        let b = Url::parse("file:///notes/b.md")?;
+        let model = BertModel::default();
+        let index = SectionIndex::default();
+        index.embed(
+            &a,
+            &Document::parse("# A\n\nOne.\n\n# B\n\nTwo.\n")?,
+            &model,
+        )?;
+        index.embed(&b, &Document::parse("# C\n\nThree.\n")?, &model)?;
+
+        index.remove(&a);
+        assert!(!index.documents.contains_key(a.as_str()));
+        assert!(!index.lexical.lock().unwrap().contains(a.as_str()));
+        assert_eq!(1, index.ann.lock().unwrap().len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn load_should_merge_missing_documents() -> anyhow::Result<()> {
+        let a = Url::parse("file:///notes/a.md")?;
+        let b = Url::parse("file:///notes/b.md")?;
+        let c = Url::parse("file:///other/c.md")?;
+        let model = BertModel::default();
+        let index = SectionIndex::default();
+        index.embed(&a, &Document::parse("# A\n\nOld.\n")?, &model)?;
+        index.embed(&b, &Document::parse("# B\n\nTwo.\n")?, &model)?;
+        index.embed(&c, &Document::parse("# C\n\nThree.\n")?, &model)?;
+
+        let path = std::env::temp_dir()
+            .join(format!("lsp-md-merge-{}.cbor", std::process::id()));
+        index.save(&path, |it| it.path().starts_with("/notes/"))?;
+
+        let shared = SectionIndex::default();
+        let current = Document::parse("# A\n\nNew.\n\n# A2\n\nMore.\n")?;
+        shared.embed(&a, &current, &model)?;
+        shared.load(&path)?;
+        fs::remove_file(&path)?;
+
+        assert_eq!(2, shared.documents.get(a.as_str()).unwrap().len());
+        assert!(shared.documents.contains_key(b.as_str()));
+        assert!(!shared.documents.contains_key(c.as_str()));
+        assert_eq!(3, shared.ann.lock().unwrap().len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn approximate_rank_should_match_linear_scan() -> anyhow::Result<()> {
+        let workspace = Workspace::default();
+        let a = Url::parse("file:///notes/a.md")?;
+        let b = Url::parse("file:///notes/b.md")?;
+        workspace.insert(
+            &a,
+            Document::parse("# Rust\n\nBorrow checker.\n\n# Tea\n\nGreen.\n")?,
+        );
+        workspace.insert(&b, Document::parse("# Cooking\n\nPasta recipe.\n")?);
+        let model = BertModel::default();
+        let index = SectionIndex::default();
+        let query = model.encode("borrow checker in rust")?;
+
+        let opts = SearchOptions {
+            limit: Some(2),
+            ..Default::default()
+        };
+        let exact = index.rank(&workspace, &model, &a, &query, None, &opts)?;
+        let opts = SearchOptions {
+            ef: Some(16),
+            ..opts
+        };
+        let approx = index.rank(&workspace, &model, &a, &query, None, &opts)?;
+        assert_eq!(
+            exact.iter().map(|it| &it.location).collect::<Vec<_>>(),
+            approx.iter().map(|it| &it.location).collect::<Vec<_>>()
+        );
+
+        workspace.insert(&a, Document::parse("# Tea\n\nGreen.\n")?);
+        let approx = index.rank(&workspace, &model, &a, &query, None, &opts)?;
+        assert_eq!(2, approx.len());
+        assert!(approx.iter().all(|it| it.title != "Rust"));
+
+        let path = std::env::temp_dir()
+            .join(format!("lsp-md-section-index-{}.cbor", std::process::id()));
+        index.save(&path, |_| true)?;
+        let loaded = SectionIndex::default();
+        loaded.load(&path)?;
+        fs::remove_file(&path)?;
+        let res = loaded.rank(&workspace, &model, &a, &query, None, &opts)?;
+        assert_eq!(
+            approx.iter().map(|it| &it.location).collect::<Vec<_>>(),
+            res.iter().map(|it| &it.location).collect::<Vec<_>>()
+        );
+        assert_eq!(
+            approx.iter().map(|it| it.score).collect::<Vec<_>>(),
+            res.iter().map(|it| it.score).collect::<Vec<_>>()
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn rank_should_point_at_best_chunk() -> anyhow::Result<()> {
+        let workspace = Workspace::default();
+        let uri = Url::parse("file:///notes/a.md")?;
+        let filler = "Boil the pasta in salted water until tender. ".repeat(12);
+        let text = format!(
+            "# Notes\n\n{filler}\n\n{filler}\n\nThe borrow checker rejects \
+             two mutable references.\n"
+        );
+        workspace.insert(&uri, Document::parse(&text)?);
+        let model = BertModel::default();
+        let index = SectionIndex::default();
+        let query = model.encode("borrow checker mutable references")?;
+
+        let opts = SearchOptions {
+            pooling: Pooling::Max,
+            ..Default::default()
+        };
+        let max = index.rank(&workspace, &model, &uri, &query, None, &opts)?;
+        let chunk = max[0].chunk.expect("section should be chunked");
+        assert_eq!(6, chunk.start.line);
+
+        let mean = index.rank(
+            &workspace,
+            &model,
+            &uri,
+            &query,
+            None,
+            &Default::default(),
+        )?;
+        assert!(mean[0].score < max[0].score);
+        assert_eq!(Some(chunk), mean[0].chunk);
+
+        // Short sections closer to the mean must not push the chunked section
+        // out before re-scoring.
+        let other = Url::parse("file:///notes/b.md")?;
+        workspace.insert(
+            &other,
+            Document::parse(&"# Borrow\n\nMutable pasta.\n\n".repeat(6))?,
+        );
+        let opts = SearchOptions {
+            limit: Some(1),
+            ..opts
+        };
+        let res = index.rank(&workspace, &model, &uri, &query, None, &opts)?;
+        assert_eq!(uri, res[0].location.uri);
+
+        Ok(())
+    }
+
+    #[test]
+    fn rank_should_exclude_source_and_diversify() -> anyhow::Result<()> {
+        let workspace = Workspace::default();
+        let uri = Url::parse("file:///notes/daily.md")?;
+        workspace.insert(
+            &uri,
+            Document::parse(
+                "# Standup\n\nRust borrow checker fixes.\n\n\
+                 # Monday\n\nRust borrow checker fixes again.\n\n\
+                 # Tuesday\n\nRust borrow checker fixes again.\n\n\
+                 # Wednesday\n\nRust compiler upgrade.\n",
+            )?,
+        );
+        let model = BertModel::default();
+        let index = SectionIndex::default();
+        let source = Location::new(uri.clone(), Range::default());
+        let query =
+            index.embed(&uri, workspace.get(&uri).unwrap().value(), &model)?[0]
+                .clone();
+
+        let opts = SearchOptions {
+            limit: Some(2),
+            ..Default::default()
+        };
+        let titles = |opts: &SearchOptions| -> anyhow::Result<Vec<String>> {
+            Ok(index
+                .rank(&workspace, &model, &uri, &query, Some(&source), opts)?
+                .into_iter()
+                .map(|it| it.title.into_owned())
+                .collect())
+        };
+        assert_eq!(vec!["Monday", "Tuesday"], titles(&opts)?);
+
+        let opts = SearchOptions {
+            mmr_lambda: Some(0.5),
+            ..opts
+        };
+        assert_eq!(vec!["Monday", "Wednesday"], titles(&opts)?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn rank_hybrid_should_report_components() -> anyhow::Result<()> {
+        let workspace = Workspace::default();
+        let uri = Url::parse("file:///notes/a.md")?;
+        workspace.insert(
+            &uri,
+            Document::parse(
+                "# Build\n\nFailed with E0433.\n\n# Lunch\n\nSandwich.\n",
+            )?,
+        );
+        let model = BertModel::default();
+        let index = SectionIndex::default();
+        let query = model.encode("E0433")?;
+
+        let res = index.rank_hybrid(
+            &workspace,
+            &model,
+            &uri,
+            "E0433",
+            &query,
+            &SearchOptions::default(),
+        )?;
+        assert_eq!(2, res.len());
+        assert_eq!("Build", res[0].title);
+        assert!(res[0].lexical_score.unwrap() > 0.0);
+        assert_eq!(Some(0.0), res[1].lexical_score);
+        assert!(res[0].score <= 1.0);
+
+        Ok(())
+    }
+}
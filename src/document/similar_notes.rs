@@ -1,10 +1,11 @@
 use std::borrow::Cow;
 
 use serde::{Deserialize, Serialize};
-use tower_lsp::lsp_types::{Location, Position, Range, Url};
+use tower_lsp::lsp_types::{Location, Range};
 
-use super::document::DocumentExt;
-use super::document_adapter::DocumentLsp;
+use super::document_adapter::{DocumentLsp, LspAdapter};
+use super::section_index::{Granularity, SearchOptions, SectionIndex};
+use super::workspace::Workspace;
 use super::Encoder;
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -12,54 +13,81 @@ pub struct ScoredLocation<'a> {
     pub score: f32,
     pub title: Cow<'a, str>,
     pub location: Location,
+    /// Component scores of hybrid rankings.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vector_score: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lexical_score: Option<f32>,
+    /// Best matching chunk of a section too long to embed in one piece.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chunk: Option<Range>,
 }
 
-pub fn find_similar<'a, D>(
-    uri: Url,
-    doc: &'a D,
+impl ScoredLocation<'_> {
+    /// One line summary for clients without a custom results view.
+    pub fn label(&self) -> String {
+        let file = self
+            .location
+            .uri
+            .path_segments()
+            .and_then(|mut it| it.next_back())
+            .unwrap_or_default();
+        format!("{} — {} ({:.2})", self.title, file, self.score)
+    }
+}
+
+/// Sections across the search scope most similar to the section at the
+/// given location, or paragraphs most similar to the paragraph there.
+pub fn find_similar(
+    workspace: &Workspace,
+    index: &SectionIndex,
     enc: &impl Encoder,
-    pos: &Position,
-) -> Vec<ScoredLocation<'a>>
-where
-    D: DocumentLsp + DocumentExt<'a>,
-{
-    let current_section_idx = doc.position_to_section(pos).unwrap();
-    let text: Cow<'a, str> = DocumentExt::text(doc, current_section_idx)
-        .expect("should have section")
-        .into();
-    let current_section_embedding = enc.encode(text).unwrap();
-    let mut sections: Vec<_> = doc
-        .sections()
-        .as_ref()
-        .into_iter()
-        .enumerate()
-        .map(|(i, _)| {
-            let t2: Cow<'a, str> = DocumentExt::text(doc, i)
-                .expect("should have section")
-                .into();
-            let embedding = enc.encode(t2).unwrap();
-            let dist = embedding.cos(&current_section_embedding);
-            (dist, i)
-        })
-        .collect();
-    sections.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap());
-    sections
-        .into_iter()
-        .take(11)
-        .map(|(score, i)| {
-            let title =
-                DocumentExt::title(doc, i).expect("should have section");
-            let range = doc.section_to_title_range(i).unwrap();
-            ScoredLocation {
-                score,
-                title: title.into(),
-                location: Location {
-                    uri: uri.clone(),
-                    range,
-                },
-            }
-        })
-        .collect()
+    loc: &Location,
+    opts: &SearchOptions,
+) -> anyhow::Result<Vec<ScoredLocation<'static>>> {
+    if opts.granularity == Granularity::Paragraph {
+        return find_similar_paragraphs(workspace, index, enc, loc, opts);
+    }
+
+    let query = {
+        let doc = workspace
+            .get(&loc.uri)
+            .ok_or_else(|| anyhow::anyhow!("unknown document: {}", loc.uri))?;
+        let current_section_idx = doc
+            .position_to_section(&loc.range.start)
+            .ok_or_else(|| anyhow::anyhow!("no section at {:?}", loc.range))?;
+        index
+            .embed(&loc.uri, doc.value(), enc)?
+            .swap_remove(current_section_idx)
+    };
+
+    index.rank(workspace, enc, &loc.uri, &query, Some(loc), opts)
+}
+
+fn find_similar_paragraphs(
+    workspace: &Workspace,
+    index: &SectionIndex,
+    enc: &impl Encoder,
+    loc: &Location,
+    opts: &SearchOptions,
+) -> anyhow::Result<Vec<ScoredLocation<'static>>> {
+    let query = {
+        let doc = workspace
+            .get(&loc.uri)
+            .ok_or_else(|| anyhow::anyhow!("unknown document: {}", loc.uri))?;
+        let offset =
+            doc.position_to_offset(&loc.range.start).ok_or_else(|| {
+                anyhow::anyhow!("invalid position {:?}", loc.range)
+            })?;
+        index
+            .embed_paragraphs(&loc.uri, doc.value(), enc)?
+            .into_iter()
+            .find(|(range, _)| range.contains(&offset))
+            .map(|(_, it)| it)
+            .ok_or_else(|| anyhow::anyhow!("no paragraph at {:?}", loc.range))?
+    };
+
+    index.rank_paragraphs(workspace, enc, &loc.uri, &query, Some(loc), opts)
 }
 
 pub fn query_section_titles<D>(doc: &D) -> Vec<Range>
@@ -70,3 +98,28 @@ where
         .map(|s| doc.section_to_title_range(s).unwrap())
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use tower_lsp::lsp_types::{Position, Url};
+
+    use super::*;
+
+    #[test]
+    fn label_should_show_title_file_and_score() -> anyhow::Result<()> {
+        let pos = Position::new(0, 2);
+        let it = ScoredLocation {
+            score: 0.8125,
+            title: Cow::Borrowed("Setup"),
+            location: Location::new(
+                Url::parse("file:///notes/daily/a.md")?,
+                Range::new(pos, pos),
+            ),
+            vector_score: None,
+            lexical_score: None,
+            chunk: None,
+        };
+        assert_eq!("Setup — a.md (0.81)", it.label());
+        Ok(())
+    }
+}
@@ -0,0 +1,489 @@
+use std::collections::BTreeSet;
+use std::ops::Range;
+
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tower_lsp::lsp_types::{
+    CodeAction, CodeActionKind, Position, Range as LspRange, TextEdit, Url,
+};
+
+use super::bert::{Embedding, Keywords};
+use super::document::{BasicDocument, SliceAccess, SyntaxTree};
+use super::document_adapter::{DocumentLsp, LspAdapter};
+use super::keyword_index::KeywordIndex;
+use super::paragraphs::paragraphs;
+use super::workspace::Workspace;
+use super::Encoder;
+
+/// Tags proposed per section.
+const MAX_TAGS: usize = 3;
+/// Keywords at least this similar to a workspace tag are replaced by it.
+const TAG_MATCH_SCORE: f32 = 0.8;
+
+/// Embeddings of tags and tag candidates by tag, so the workspace vocabulary
+/// is only encoded once.
+#[derive(Default)]
+pub struct TagEmbeddings(DashMap<String, Embedding>);
+
+impl TagEmbeddings {
+    /// Embedding of each tag, encoding the ones not seen before.
+    fn embed(
+        &self,
+        enc: &impl Encoder,
+        tags: &[String],
+    ) -> anyhow::Result<Vec<Embedding>> {
+        let missing: Vec<&String> = tags
+            .iter()
+            .filter(|it| !self.0.contains_key(*it))
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect();
+        if !missing.is_empty() {
+            let words: Vec<String> = missing
+                .iter()
+                .map(|it| it.replace(['-', '_', '/'], " "))
+                .collect();
+            for (tag, it) in missing.into_iter().zip(enc.encode_batch(&words)?)
+            {
+                self.0.insert(tag.clone(), it);
+            }
+        }
+        tags.iter()
+            .map(|it| {
+                self.0.get(it).map(|it| it.clone()).ok_or_else(|| {
+                    anyhow::anyhow!("encoder returned too few embeddings")
+                })
+            })
+            .collect()
+    }
+}
+
+/// Where a tag action applies, kept in its `data`.
+#[derive(Debug, Deserialize, Serialize)]
+struct TagData {
+    uri: Url,
+    position: Position,
+}
+
+/// For a position on a section heading, an unresolved action that tags the
+/// section with its keywords. The edit is computed by `resolve_tag_action`.
+pub fn tag_action(
+    workspace: &Workspace,
+    uri: &Url,
+    pos: &Position,
+) -> Option<CodeAction> {
+    let doc = workspace.get(uri)?;
+    let index = doc.position_to_section(pos)?;
+    let title = doc.section_to_title_range(index)?;
+    if title.start.line != pos.line {
+        return None;
+    }
+
+    Some(CodeAction {
+        title: "Add tags".to_string(),
+        kind: Some(CodeActionKind::REFACTOR),
+        data: Some(json!(TagData {
+            uri: uri.clone(),
+            position: *pos,
+        })),
+        ..Default::default()
+    })
+}
+
+/// Fill in the edit of an action from `tag_action`. Keywords close to a tag
+/// already used in the workspace are replaced by that tag. Tags go into the
+/// front matter `tags:` when the document has front matter, otherwise as a
+/// `#tag` line below the heading. The action is returned without an edit when
+/// there is nothing to add. Section keywords come from the keyword index.
+pub fn resolve_tag_action(
+    workspace: &Workspace,
+    keywords: &KeywordIndex,
+    embeddings: &TagEmbeddings,
+    enc: &(impl Encoder + Keywords),
+    mut action: CodeAction,
+) -> anyhow::Result<CodeAction> {
+    let Some(data) = action.data.clone() else {
+        return Ok(action);
+    };
+    let TagData { uri, position } = serde_json::from_value(data)?;
+    let (keywords, existing) = {
+        let Some(doc) = workspace.get(&uri) else {
+            return Ok(action);
+        };
+        let Some(index) = doc.position_to_section(&position) else {
+            return Ok(action);
+        };
+        let mut sections = keywords.keywords(&uri, doc.value(), enc)?;
+        if index >= sections.len() {
+            return Ok(action);
+        }
+        (sections.swap_remove(index), tags(doc.value()))
+    };
+    if keywords.is_empty() {
+        return Ok(action);
+    }
+
+    let vocabulary: Vec<String> = workspace
+        .uris()
+        .iter()
+        .filter_map(|it| workspace.get(it).map(|doc| tags(doc.value())))
+        .flatten()
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect();
+    let candidates: Vec<String> =
+        keywords.iter().map(|it| slug(&it.text)).collect();
+    let matched =
+        vocabulary_matches(embeddings, enc, &candidates, &vocabulary)?;
+
+    let mut proposed: Vec<String> = Vec::new();
+    for tag in matched {
+        if !tag.is_empty() &&
+            !existing.contains(&tag) &&
+            !proposed.contains(&tag)
+        {
+            proposed.push(tag);
+        }
+        if proposed.len() == MAX_TAGS {
+            break;
+        }
+    }
+    if proposed.is_empty() {
+        return Ok(action);
+    }
+
+    let edit = workspace.get(&uri).and_then(|doc| {
+        let index = doc.position_to_section(&position)?;
+        tag_edit(doc.value(), index, &proposed)
+    });
+    let Some(edit) = edit else {
+        return Ok(action);
+    };
+    action.title = format!(
+        "Add tags: {}",
+        proposed
+            .iter()
+            .map(|it| format!("#{}", it))
+            .collect::<Vec<_>>()
+            .join(" ")
+    );
+    action.edit = Some(workspace.edit(vec![(uri, vec![edit])]));
+    Ok(action)
+}
+
+/// Each candidate, or the most similar vocabulary tag when it is close enough.
+fn vocabulary_matches(
+    embeddings: &TagEmbeddings,
+    enc: &impl Encoder,
+    candidates: &[String],
+    vocabulary: &[String],
+) -> anyhow::Result<Vec<String>> {
+    if vocabulary.is_empty() {
+        return Ok(candidates.to_vec());
+    }
+    let vocabulary_embeddings = embeddings.embed(enc, vocabulary)?;
+    let candidate_embeddings = embeddings.embed(enc, candidates)?;
+
+    Ok(candidates
+        .iter()
+        .zip(candidate_embeddings)
+        .map(|(candidate, embedding)| {
+            vocabulary
+                .iter()
+                .zip(&vocabulary_embeddings)
+                .map(|(tag, it)| (tag, it.cos(&embedding)))
+                .filter(|(_, score)| *score >= TAG_MATCH_SCORE)
+                .max_by(|a, b| a.1.total_cmp(&b.1))
+                .map_or_else(|| candidate.clone(), |(tag, _)| tag.clone())
+        })
+        .collect())
+}
+
+/// Tags of a document, from its front matter and inline `#tag`s.
+pub fn tags<D: SyntaxTree + SliceAccess>(doc: &D) -> BTreeSet<String> {
+    let mut ret = BTreeSet::new();
+    if let Some(range) = front_matter(doc) {
+        let text = doc.slice(range);
+        if let Some((_, values)) = front_matter_tags(&text) {
+            ret.extend(values);
+        }
+    }
+    for range in paragraphs(doc) {
+        ret.extend(inline_tags(&doc.slice(range)));
+    }
+    ret
+}
+
+/// Byte range of the YAML front matter.
+pub(super) fn front_matter<D: SyntaxTree>(doc: &D) -> Option<Range<usize>> {
+    let mut node = doc.tree().root_node();
+    while node.kind() == "document" || node.kind() == "section" {
+        node = node.named_child(0)?;
+    }
+    (node.kind() == "minus_metadata").then(|| node.byte_range())
+}
+
+/// The `tags:` entry of front matter text: byte range of the entry and its
+/// values. Values are a flow list, a comma separated list or `- tag` lines.
+fn front_matter_tags(text: &str) -> Option<(Range<usize>, Vec<String>)> {
+    let mut offset = 0;
+    let mut lines = text.split_inclusive('\n').map(|line| {
+        let start = offset;
+        offset += line.len();
+        (start, line)
+    });
+    let (start, line) = lines.find(|(_, it)| it.starts_with("tags:"))?;
+    let value = line["tags:".len()..].trim();
+    let mut end = start + line.trim_end().len();
+
+    let values: Vec<&str> = if value.is_empty() {
+        let mut values = Vec::new();
+        for (start, line) in lines {
+            let Some(item) = line.trim_start().strip_prefix("- ") else {
+                break;
+            };
+            values.push(item.trim());
+            end = start + line.trim_end().len();
+        }
+        values
+    } else {
+        value
+            .trim_start_matches('[')
+            .trim_end_matches(']')
+            .split(',')
+            .map(|it| it.trim())
+            .collect()
+    };
+    let values = values
+        .into_iter()
+        .map(|it| it.trim_matches(['"', '\'']).to_string())
+        .filter(|it| !it.is_empty())
+        .collect();
+    Some((start..end, values))
+}
+
+/// `#tag` words of a paragraph, without the `#`.
+fn inline_tags(text: &str) -> Vec<String> {
+    text.split_whitespace()
+        .filter_map(|it| it.strip_prefix('#'))
+        .map(|it| {
+            it.trim_end_matches(|c: char| !c.is_alphanumeric())
+                .to_string()
+        })
+        .filter(|it| {
+            it.chars()
+                .all(|c| c.is_alphanumeric() || matches!(c, '-' | '_' | '/')) &&
+                it.chars().any(|c| c.is_alphabetic())
+        })
+        .collect()
+}
+
+/// A keyword as a tag: lowercase words joined by `-`.
+fn slug(keyword: &str) -> String {
+    keyword
+        .split_whitespace()
+        .map(|word| {
+            word.chars()
+                .filter(|c| c.is_alphanumeric() || matches!(c, '-' | '_'))
+                .flat_map(char::to_lowercase)
+                .collect::<String>()
+        })
+        .filter(|it| !it.is_empty())
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+/// Insert `tags` into the front matter `tags:` entry, a new entry at the end
+/// of the front matter, or a `#tag` line below the heading of the section.
+fn tag_edit<D>(doc: &D, index: usize, tags: &[String]) -> Option<TextEdit>
+where
+    D: SyntaxTree + SliceAccess + BasicDocument + LspAdapter,
+{
+    let insert = |offset: usize, text: String| {
+        let pos = doc.offset_to_position(offset)?;
+        Some(TextEdit::new(LspRange::new(pos, pos), text))
+    };
+
+    if let Some(range) = front_matter(doc) {
+        let text = doc.slice(range.clone());
+        let Some((entry, _)) = front_matter_tags(&text) else {
+            // Before the closing `---`.
+            let end = text.trim_end().rfind('\n')? + 1;
+            return insert(
+                range.start + end,
+                format!("tags: [{}]\n", tags.join(", ")),
+            );
+        };
+        let entry_text = &text[entry.clone()];
+        let end = range.start + entry.end;
+        return if entry_text.contains('\n') {
+            let last = entry_text.lines().last()?;
+            let indent = &last[..last.len() - last.trim_start().len()];
+            insert(
+                end,
+                tags.iter()
+                    .map(|it| format!("\n{}- {}", indent, it))
+                    .collect(),
+            )
+        } else if entry_text.ends_with("[]") {
+            insert(end - 1, tags.join(", "))
+        } else if entry_text.ends_with(']') {
+            insert(end - 1, format!(", {}", tags.join(", ")))
+        } else if entry_text.trim_end() == "tags:" {
+            insert(end, format!(" [{}]", tags.join(", ")))
+        } else {
+            insert(end, format!(", {}", tags.join(", ")))
+        };
+    }
+
+    // Below the heading line, or the underline of a setext heading.
+    let sections = doc.sections();
+    let section = &sections.as_ref()[index];
+    let rest = doc.slice(section.title.end..section.range.end);
+    let mut end = section.title.end + rest.find('\n').unwrap_or(rest.len());
+    let next = rest.split('\n').nth(1).unwrap_or_default().trim();
+    if !next.is_empty() && next.chars().all(|c| c == '=' || c == '-') {
+        end += 1 + rest.split('\n').nth(1).unwrap_or_default().len();
+    }
+    insert(
+        end,
+        format!(
+            "\n\n{}",
+            tags.iter()
+                .map(|it| format!("#{}", it))
+                .collect::<Vec<_>>()
+                .join(" ")
+        ),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    use tower_lsp::lsp_types::{DocumentChanges, OneOf};
+
+    use super::*;
+    use crate::document::bert::Keyword;
+    use crate::document::{BertModel, Document};
+
+    /// Counts the texts it encodes.
+    #[derive(Default)]
+    struct Counting {
+        model: BertModel,
+        texts: AtomicUsize,
+    }
+
+    impl Encoder for Counting {
+        fn encode_batch<S: AsRef<str> + Sync>(
+            &self,
+            sentences: &[S],
+        ) -> anyhow::Result<Vec<Embedding>> {
+            self.texts.fetch_add(sentences.len(), Ordering::SeqCst);
+            self.model.encode_batch(sentences)
+        }
+    }
+
+    impl Keywords for Counting {
+        fn extract_batch(
+            &self,
+            texts: &[&str],
+        ) -> anyhow::Result<Vec<Vec<Keyword>>> {
+            self.model.extract_batch(texts)
+        }
+    }
+
+    #[test]
+    fn tags_should_be_read_from_front_matter_and_text() -> anyhow::Result<()> {
+        let doc = Document::parse(
+            "---\ntitle: Note\ntags:\n  - rust\n  - \"async\"\n---\n\n# \
+             Title\n\nSee #borrow-checker and #42, not a#b.\n",
+        )?;
+        assert_eq!(
+            vec!["async", "borrow-checker", "rust"],
+            tags(&doc).into_iter().collect::<Vec<_>>()
+        );
+
+        assert_eq!(
+            Some((0..14, vec!["a".to_string(), "b".to_string()])),
+            front_matter_tags("tags: [a, \"b\"]\n")
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn tag_edit_should_follow_front_matter_style() -> anyhow::Result<()> {
+        let tags = vec!["rust".to_string(), "cargo".to_string()];
+        let edit = |text: &str| -> anyhow::Result<String> {
+            let doc = Document::parse(text)?;
+            let edit = tag_edit(&doc, 0, &tags).unwrap();
+            let offset = doc.position_to_offset(&edit.range.start).unwrap();
+            Ok(format!(
+                "{}{}{}",
+                &text[..offset],
+                edit.new_text,
+                &text[offset..]
+            ))
+        };
+
+        assert_eq!(
+            "---\ntags: [notes, rust, cargo]\n---\n# T\n",
+            edit("---\ntags: [notes]\n---\n# T\n")?
+        );
+        assert_eq!(
+            "---\ntags:\n- notes\n- rust\n- cargo\n---\n# T\n",
+            edit("---\ntags:\n- notes\n---\n# T\n")?
+        );
+        assert_eq!(
+            "---\ntitle: T\ntags: [rust, cargo]\n---\n# T\n",
+            edit("---\ntitle: T\n---\n# T\n")?
+        );
+        assert_eq!("# T\n\n#rust #cargo\n\nBody\n", edit("# T\n\nBody\n")?);
+        assert_eq!("T\n=\n\n#rust #cargo\n\nBody\n", edit("T\n=\n\nBody\n")?);
+        Ok(())
+    }
+
+    #[test]
+    fn tag_action_should_prefer_workspace_tags() -> anyhow::Result<()> {
+        let workspace = Workspace::default();
+        let a = Url::parse("file:///notes/a.md")?;
+        workspace.insert(
+            &a,
+            Document::parse("# Cargo\n\nCargo builds rust crates.\n")?,
+        );
+        workspace.insert(
+            &Url::parse("file:///notes/b.md")?,
+            Document::parse("# Other\n\nTagged #crates here.\n")?,
+        );
+        let enc = Counting::default();
+        let keywords = KeywordIndex::default();
+        let embeddings = TagEmbeddings::default();
+        let resolve = |action| {
+            resolve_tag_action(&workspace, &keywords, &embeddings, &enc, action)
+        };
+
+        assert!(tag_action(&workspace, &a, &Position::new(2, 0)).is_none());
+        let action = tag_action(&workspace, &a, &Position::new(0, 3)).unwrap();
+        assert!(action.edit.is_none());
+        let action = resolve(action)?;
+        let encoded = enc.texts.load(Ordering::SeqCst);
+        assert!(encoded > 0);
+        let again = tag_action(&workspace, &a, &Position::new(0, 3)).unwrap();
+        assert_eq!(action.title, resolve(again)?.title);
+        assert_eq!(encoded, enc.texts.load(Ordering::SeqCst));
+
+        let Some(DocumentChanges::Edits(changes)) =
+            action.edit.unwrap().document_changes
+        else {
+            panic!("expected versioned document edits");
+        };
+        let OneOf::Left(edit) = &changes[0].edits[0] else {
+            panic!("expected a plain text edit");
+        };
+        assert_eq!(Position::new(0, 7), edit.range.start);
+        assert!(edit.new_text.contains("#crates"));
+
+        Ok(())
+    }
+}
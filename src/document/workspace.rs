@@ -0,0 +1,280 @@
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::{fs, io};
+
+use dashmap::mapref::one::Ref;
+use dashmap::DashMap;
+use regex::Regex;
+use tower_lsp::lsp_types::{
+    DocumentChanges, OneOf, OptionalVersionedTextDocumentIdentifier,
+    TextDocumentEdit, TextEdit, Url, WorkspaceEdit,
+};
+
+use super::document::SliceAccess;
+use super::Document;
+
+/// Markdown documents of the workspace, keyed by uri. Open buffers shadow the
+/// copies indexed from disk.
+#[derive(Default)]
+pub struct Workspace {
+    roots: Mutex<Vec<PathBuf>>,
+    open: DashMap<String, Document>,
+    /// Client version of each open buffer.
+    versions: DashMap<String, i32>,
+    indexed: DashMap<String, Document>,
+    /// Pattern of lines starting sections, instead of markdown headings.
+    headings: Mutex<Option<Regex>>,
+}
+
+impl Workspace {
+    pub fn set_roots(&self, mut roots: Vec<PathBuf>) {
+        roots.sort();
+        roots.dedup();
+        *self.roots.lock().unwrap() = roots;
+    }
+
+    pub fn roots(&self) -> Vec<PathBuf> {
+        self.roots.lock().unwrap().clone()
+    }
+
+    /// Whether `uri` is a file under one of the roots.
+    pub fn in_roots(&self, uri: &Url) -> bool {
+        let Ok(path) = uri.to_file_path() else {
+            return false;
+        };
+        self.roots
+            .lock()
+            .unwrap()
+            .iter()
+            .any(|root| path.starts_with(root))
+    }
+
+    /// Start sections at lines matching `headings`, or at markdown headings
+    /// without one, and parse every document again.
+    pub fn set_headings(&self, headings: Option<Regex>) {
+        *self.headings.lock().unwrap() = headings;
+        for docs in [&self.open, &self.indexed] {
+            for mut it in docs.iter_mut() {
+                let text = it.value().slice(..).into_owned();
+                if let Ok(doc) = self.parse(&text) {
+                    *it = doc;
+                }
+            }
+        }
+    }
+
+    /// Parse a document with the workspace's heading pattern.
+    pub fn parse(&self, text: &str) -> anyhow::Result<Document> {
+        Document::with_headings(text, self.headings.lock().unwrap().as_ref())
+    }
+
+    /// Store the latest content of an open buffer.
+    pub fn insert(&self, uri: &Url, doc: Document) {
+        self.open.insert(uri.to_string(), doc);
+    }
+
+    /// Store the content of an open buffer at `version`. Returns false, and
+    /// keeps the current content, when `version` is older than the stored
+    /// one.
+    pub fn update(&self, uri: &Url, version: i32, doc: Document) -> bool {
+        let key = uri.to_string();
+        if self.versions.get(&key).is_some_and(|it| *it > version) {
+            return false;
+        }
+        self.versions.insert(key, version);
+        self.insert(uri, doc);
+        true
+    }
+
+    /// Drop the open buffer, falling back to the content on disk.
+    pub fn close(&self, uri: &Url) -> anyhow::Result<()> {
+        self.open.remove(uri.as_str());
+        self.versions.remove(uri.as_str());
+        match uri.to_file_path() {
+            Ok(path) if path.is_file() => self.index_file(&path),
+            _ => Ok(()),
+        }
+    }
+
+    /// Forget the on-disk copy of a deleted file. An open buffer stays.
+    pub fn remove(&self, uri: &Url) {
+        self.indexed.remove(uri.as_str());
+    }
+
+    /// Uris of open buffers.
+    pub fn open_uris(&self) -> Vec<Url> {
+        self.open
+            .iter()
+            .filter_map(|it| Url::parse(it.key()).ok())
+            .collect()
+    }
+
+    /// Client version of an open buffer.
+    pub fn version(&self, uri: &Url) -> Option<i32> {
+        self.versions.get(uri.as_str()).map(|it| *it)
+    }
+
+    /// A workspace edit tagged with the versions the edits were computed
+    /// against, so clients reject it once a buffer has changed.
+    pub fn edit(&self, changes: Vec<(Url, Vec<TextEdit>)>) -> WorkspaceEdit {
+        let edits = changes
+            .into_iter()
+            .map(|(uri, edits)| TextDocumentEdit {
+                text_document: OptionalVersionedTextDocumentIdentifier {
+                    version: self.version(&uri),
+                    uri,
+                },
+                edits: edits.into_iter().map(OneOf::Left).collect(),
+            })
+            .collect();
+        WorkspaceEdit {
+            document_changes: Some(DocumentChanges::Edits(edits)),
+            ..Default::default()
+        }
+    }
+
+    pub fn get(&self, uri: &Url) -> Option<Ref<'_, String, Document>> {
+        self.open
+            .get(uri.as_str())
+            .or_else(|| self.indexed.get(uri.as_str()))
+    }
+
+    /// Current text of a document, open or indexed.
+    pub fn text(&self, uri: &Url) -> Option<String> {
+        self.get(uri).map(|it| it.value().slice(..).into_owned())
+    }
+
+    /// Uris of every known document, open or indexed.
+    pub fn uris(&self) -> Vec<Url> {
+        let mut ret: Vec<String> =
+            self.open.iter().map(|it| it.key().clone()).collect();
+        ret.extend(
+            self.indexed
+                .iter()
+                .map(|it| it.key().clone())
+                .filter(|it| !self.open.contains_key(it)),
+        );
+        ret.into_iter()
+            .filter_map(|it| Url::parse(&it).ok())
+            .collect()
+    }
+
+    /// Markdown files under every root, skipping hidden and build
+    /// directories. Symlinked directories are not followed. Directories that
+    /// cannot be read are skipped and returned with their error.
+    pub fn files(&self) -> (Vec<PathBuf>, Vec<(PathBuf, io::Error)>) {
+        let mut ret = Vec::new();
+        let mut errors = Vec::new();
+        for root in self.roots() {
+            collect_files(&root, &mut ret, &mut errors);
+        }
+        (ret, errors)
+    }
+
+    pub fn index_file(&self, path: &Path) -> anyhow::Result<()> {
+        let uri = Url::from_file_path(path)
+            .map_err(|_| anyhow::anyhow!("invalid path: {:?}", path))?;
+        let doc = self.parse(&fs::read_to_string(path)?)?;
+        self.indexed.insert(uri.to_string(), doc);
+        Ok(())
+    }
+}
+
+fn collect_files(
+    dir: &Path,
+    ret: &mut Vec<PathBuf>,
+    errors: &mut Vec<(PathBuf, io::Error)>,
+) {
+    let entries = match fs::read_dir(dir) {
+        Ok(it) => it,
+        Err(err) => return errors.push((dir.to_path_buf(), err)),
+    };
+    for entry in entries {
+        let (path, file_type) =
+            match entry.and_then(|it| Ok((it.path(), it.file_type()?))) {
+                Ok(it) => it,
+                Err(err) => {
+                    errors.push((dir.to_path_buf(), err));
+                    continue;
+                },
+            };
+        if is_ignored(&path) {
+            continue;
+        }
+        if file_type.is_dir() {
+            collect_files(&path, ret, errors);
+        } else if path.extension().is_some_and(|it| it == "md") {
+            ret.push(path);
+        }
+    }
+}
+
+fn is_ignored(path: &Path) -> bool {
+    match path.file_name().and_then(|it| it.to_str()) {
+        Some(name) => {
+            name.starts_with('.') || name == "node_modules" || name == "target"
+        },
+        None => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::document::SliceAccess;
+
+    #[test]
+    fn open_buffers_should_shadow_indexed() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir()
+            .join(format!("lsp-md-workspace-test-{}", std::process::id()));
+        fs::create_dir_all(dir.join("sub"))?;
+        fs::write(dir.join("a.md"), "# On disk\n")?;
+        fs::write(dir.join("sub/b.md"), "# Nested\n")?;
+        fs::write(dir.join("c.txt"), "ignored")?;
+        #[cfg(unix)]
+        std::os::unix::fs::symlink(&dir, dir.join("sub/loop"))?;
+
+        let workspace = Workspace::default();
+        workspace.set_roots(vec![dir.clone()]);
+        let (files, errors) = workspace.files();
+        assert_eq!(2, files.len());
+        assert!(errors.is_empty());
+        for path in &files {
+            workspace.index_file(path)?;
+        }
+
+        let uri = Url::from_file_path(dir.join("a.md")).unwrap();
+        assert_eq!("# On disk\n", workspace.get(&uri).unwrap().slice(0..));
+
+        workspace.insert(&uri, Document::parse("# In editor\n")?);
+        assert_eq!("# In editor\n", workspace.get(&uri).unwrap().slice(0..));
+        assert_eq!(2, workspace.uris().len());
+
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn closed_buffers_should_fall_back_to_disk() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir().join(format!(
+            "lsp-md-workspace-close-test-{}",
+            std::process::id()
+        ));
+        fs::create_dir_all(&dir)?;
+        fs::write(dir.join("a.md"), "# Saved\n")?;
+        let uri = Url::from_file_path(dir.join("a.md")).unwrap();
+        let workspace = Workspace::default();
+
+        assert!(workspace.update(&uri, 2, Document::parse("# Two\n")?));
+        assert!(!workspace.update(&uri, 1, Document::parse("# One\n")?));
+        assert_eq!("# Two\n", workspace.get(&uri).unwrap().slice(0..));
+        assert_eq!(Some(2), workspace.version(&uri));
+
+        workspace.close(&uri)?;
+        assert_eq!(None, workspace.version(&uri));
+        assert_eq!("# Saved\n", workspace.get(&uri).unwrap().slice(0..));
+
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,202 @@
+use std::collections::HashSet;
+
+use tower_lsp::lsp_types::{
+    Location, Range, SymbolInformation, SymbolKind, Url,
+};
+
+use super::document::SliceAccess;
+use super::document_adapter::LspAdapter;
+use super::headings::headings;
+use super::section_index::{SearchOptions, SectionIndex};
+use super::workspace::Workspace;
+use super::Encoder;
+
+/// Below this many fuzzy matches, results are topped up with sections ranked
+/// by embedding similarity to the query.
+const MIN_FUZZY_RESULTS: usize = 5;
+const SEMANTIC_RESULTS: usize = 10;
+
+struct Entry {
+    uri: Url,
+    title: String,
+    range: Range,
+}
+
+/// Every heading in the workspace, fuzzy matched against the query with a
+/// semantic fallback ranked by the section index.
+pub fn workspace_symbols(
+    workspace: &Workspace,
+    index: &SectionIndex,
+    enc: Option<&impl Encoder>,
+    query: &str,
+) -> anyhow::Result<Vec<SymbolInformation>> {
+    let entries = entries(workspace);
+
+    let mut matched: Vec<(usize, usize)> = entries
+        .iter()
+        .enumerate()
+        .filter_map(|(i, it)| Some((fuzzy_score(query, &it.title)?, i)))
+        .collect();
+    matched.sort_by(|a, b| {
+        b.0.cmp(&a.0)
+            .then(entries[a.1].title.len().cmp(&entries[b.1].title.len()))
+    });
+    let mut ret: Vec<usize> = matched.into_iter().map(|(_, i)| i).collect();
+
+    let enc = enc
+        .filter(|_| !query.trim().is_empty() && ret.len() < MIN_FUZZY_RESULTS);
+    if let (Some(enc), Some(first)) = (enc, entries.first()) {
+        let opts = SearchOptions {
+            limit: Some(ret.len() + SEMANTIC_RESULTS),
+            ..Default::default()
+        };
+        let query = enc.encode(query)?;
+        let fuzzy: HashSet<usize> = ret.iter().copied().collect();
+        let ranked =
+            index.rank(workspace, enc, &first.uri, &query, None, &opts)?;
+        let semantic = ranked
+            .iter()
+            .filter_map(|hit| {
+                entries.iter().position(|it| {
+                    it.uri == hit.location.uri &&
+                        it.range.start.line == hit.location.range.start.line
+                })
+            })
+            .filter(|i| !fuzzy.contains(i))
+            .take(SEMANTIC_RESULTS);
+        ret.extend(semantic);
+    }
+
+    Ok(ret.into_iter().map(|i| symbol(&entries[i])).collect())
+}
+
+fn entries(workspace: &Workspace) -> Vec<Entry> {
+    let mut ret = Vec::new();
+    for uri in workspace.uris() {
+        let Some(doc) = workspace.get(&uri) else {
+            continue;
+        };
+        for heading in headings(doc.value()) {
+            let (Some(start), Some(end)) = (
+                doc.offset_to_position(heading.title.start),
+                doc.offset_to_position(heading.title.end),
+            ) else {
+                continue;
+            };
+            ret.push(Entry {
+                uri: uri.clone(),
+                title: doc.slice(heading.title.clone()).trim().to_string(),
+                range: Range::new(start, end),
+            });
+        }
+    }
+    ret
+}
+
+#[allow(deprecated)]
+fn symbol(entry: &Entry) -> SymbolInformation {
+    SymbolInformation {
+        name: entry.title.clone(),
+        kind: SymbolKind::NAMESPACE,
+        tags: None,
+        deprecated: None,
+        location: Location::new(entry.uri.clone(), entry.range),
+        container_name: entry
+            .uri
+            .path_segments()
+            .and_then(|mut it| it.next_back())
+            .map(|it| it.to_string()),
+    }
+}
+
+/// Case-insensitive subsequence match. Consecutive characters and matches at
+/// word starts score higher; the best alignment over all start positions wins.
+fn fuzzy_score(query: &str, text: &str) -> Option<usize> {
+    let query: Vec<char> = query
+        .chars()
+        .filter(|c| !c.is_whitespace())
+        .flat_map(char::to_lowercase)
+        .collect();
+    let text: Vec<char> = text.chars().flat_map(char::to_lowercase).collect();
+    let Some(&first) = query.first() else {
+        return Some(0);
+    };
+
+    (0..text.len())
+        .filter(|&i| text[i] == first)
+        .filter_map(|i| align(&query, &text, i))
+        .max()
+}
+
+fn align(query: &[char], text: &[char], start: usize) -> Option<usize> {
+    let mut score = 0;
+    let mut matched = 0;
+    let mut prev_matched = false;
+
+    for i in start..text.len() {
+        if matched == query.len() {
+            break;
+        }
+        if text[i] != query[matched] {
+            prev_matched = false;
+            continue;
+        }
+        matched += 1;
+        score += 1;
+        if prev_matched {
+            score += 2;
+        }
+        if i == 0 || !text[i - 1].is_alphanumeric() {
+            score += 3;
+        }
+        prev_matched = true;
+    }
+
+    (matched == query.len()).then_some(score)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::document::{BertModel, Document};
+
+    #[test]
+    fn fuzzy_score_should_prefer_contiguous_matches() {
+        assert_eq!(None, fuzzy_score("xyz", "Meeting notes"));
+        assert!(fuzzy_score("mtg", "Meeting notes").is_some());
+        assert!(
+            fuzzy_score("note", "Meeting notes") >
+                fuzzy_score("note", "Nine other tasks")
+        );
+        assert_eq!(Some(0), fuzzy_score("", "Anything"));
+    }
+
+    #[test]
+    fn workspace_symbols_should_list_headings() -> anyhow::Result<()> {
+        let workspace = Workspace::default();
+        workspace.insert(
+            &Url::parse("file:///notes/a.md")?,
+            Document::parse("# Daily notes\n\n## Meeting\n\nText\n")?,
+        );
+        workspace.insert(
+            &Url::parse("file:///notes/b.md")?,
+            Document::parse("# Reading list\n")?,
+        );
+        let model = BertModel::default();
+        let index = SectionIndex::default();
+
+        let all = workspace_symbols(&workspace, &index, Some(&model), "")?;
+        assert_eq!(3, all.len());
+
+        let res = workspace_symbols(&workspace, &index, Some(&model), "meet")?;
+        assert_eq!("Meeting", res[0].name);
+        assert_eq!(Some("a.md".to_string()), res[0].container_name);
+        assert_eq!(3, res.len());
+
+        let fuzzy =
+            workspace_symbols(&workspace, &index, None::<&BertModel>, "meet")?;
+        assert_eq!(1, fuzzy.len());
+
+        Ok(())
+    }
+}
@@ -1,35 +1,163 @@
-use std::sync::Mutex;
+use std::ops::Deref;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
+use anyhow::Context;
 use dashmap::DashMap;
+use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use tower_lsp::jsonrpc::Result;
+use tokio::sync::OnceCell;
+use tower_lsp::jsonrpc::{Error, Result};
+use tower_lsp::lsp_types::notification::Progress as ProgressNotification;
+use tower_lsp::lsp_types::request::WorkDoneProgressCreate;
 use tower_lsp::lsp_types::*;
 use tower_lsp::{Client, LanguageServer};
 
 use crate::document::{
-    extract_keywords, find_by_keyword, find_similar, query_section_titles,
-    BertModel, CodeFormatter, Document, LspRangeFormat,
+    clusters, code_lenses, duplicate_diagnostics, extract_keywords,
+    find_by_keyword, find_similar, folding_ranges, hover, lint, merge_action,
+    resolve_code_lens, resolve_tag_action, section_backlinks, tag_action,
+    workspace_symbols, BertModel, ClusterOptions, CodeFormatter, KeywordIndex,
+    LspRangeFormat, ModelType, ScoredLocation, SearchOptions, SectionIndex,
+    TagEmbeddings, Workspace,
 };
+use crate::settings::{
+    client_layer, read_config_file, Settings, SettingsLayers, CONFIG_FILE,
+    CONFIG_SECTION,
+};
+
+/// Pause in edits before a changed document is embedded again.
+const EMBED_DEBOUNCE: Duration = Duration::from_millis(500);
 
-#[derive(Debug, Eq, PartialEq, Clone, Deserialize, Serialize)]
+#[derive(Debug, PartialEq, Clone, Deserialize, Serialize)]
 struct KeywordQuery {
     pub uri: Url,
     pub keyword: String,
+    #[serde(flatten)]
+    pub options: SearchOptions,
+}
+
+/// The sentence model and indexes, shared by the connections of a server
+/// listening on a socket so they stay warm across editor sessions. Indexes
+/// are keyed by document uri and every connection ranks only the documents
+/// of its own workspace.
+#[derive(Clone, Default)]
+pub struct Shared {
+    encoder: Arc<OnceCell<LoadedModel>>,
+    index: Arc<SectionIndex>,
+    keywords: Arc<KeywordIndex>,
+    tags: Arc<TagEmbeddings>,
 }
 
-pub struct Backend {
+/// The sentence model with the type it was loaded as, which may differ from
+/// the configured one until a restart.
+struct LoadedModel {
+    model_type: ModelType,
+    model: Mutex<BertModel>,
+}
+
+pub struct Backend(Arc<State>);
+
+/// State of a connection, shared with its background tasks.
+pub struct State {
     client: Client,
-    encoder: Mutex<BertModel>,
-    document_map: DashMap<String, Document>,
+    /// Loaded on first use, see `encoder()`.
+    encoder: Arc<OnceCell<LoadedModel>>,
+    workspace: Workspace,
+    index: Arc<SectionIndex>,
+    keywords: Arc<KeywordIndex>,
+    tags: Arc<TagEmbeddings>,
+    settings: Mutex<Settings>,
+    /// Sources `settings` were merged from.
+    settings_layers: Mutex<SettingsLayers>,
+    /// Whether the client answers `workspace/configuration`.
+    pull_configuration: AtomicBool,
+    /// Whether the client accepts `workspace/codeLens/refresh`.
+    code_lens_refresh: AtomicBool,
+    /// Whether the client accepts file watchers through
+    /// `client/registerCapability`.
+    watch_files: AtomicBool,
+    /// Whether the client accepts `window/workDoneProgress/create`.
+    work_done_progress: AtomicBool,
+    /// Whether the client accepts `window/showDocument`.
+    show_document: AtomicBool,
+    /// Cancellation flags of running progress reports, by token.
+    progress: DashMap<String, Arc<AtomicBool>>,
+    next_progress: AtomicU64,
+    /// Latest embedding request of each changed document, by uri.
+    embed_requests: DashMap<String, u64>,
 }
 
 #[tower_lsp::async_trait]
 impl LanguageServer for Backend {
     async fn initialize(
         &self,
-        _: InitializeParams,
+        params: InitializeParams,
     ) -> Result<InitializeResult> {
+        let folders = params.workspace_folders.unwrap_or_default();
+        let roots = folders
+            .into_iter()
+            .map(|it| it.uri)
+            .chain(params.root_uri)
+            .filter_map(|it| it.to_file_path().ok())
+            .collect();
+        self.workspace.set_roots(roots);
+        let initialization =
+            client_layer(params.initialization_options.unwrap_or_default());
+        match initialization {
+            Ok(it) => self.settings_layers.lock().unwrap().initialization = it,
+            Err(err) => {
+                self.client
+                    .show_message(
+                        MessageType::ERROR,
+                        format!("invalid initializationOptions: {:#}", err),
+                    )
+                    .await
+            },
+        }
+        self.reload_config_file().await;
+        self.apply_settings().await;
+        let refresh = params
+            .capabilities
+            .workspace
+            .as_ref()
+            .and_then(|it| it.code_lens.as_ref())
+            .and_then(|it| it.refresh_support)
+            .unwrap_or_default();
+        self.code_lens_refresh.store(refresh, Ordering::Relaxed);
+        let progress = params
+            .capabilities
+            .window
+            .as_ref()
+            .and_then(|it| it.work_done_progress)
+            .unwrap_or_default();
+        self.work_done_progress.store(progress, Ordering::Relaxed);
+        let show = params
+            .capabilities
+            .window
+            .as_ref()
+            .and_then(|it| it.show_document.as_ref())
+            .is_some_and(|it| it.support);
+        self.show_document.store(show, Ordering::Relaxed);
+        let watch = params
+            .capabilities
+            .workspace
+            .as_ref()
+            .and_then(|it| it.did_change_watched_files.as_ref())
+            .and_then(|it| it.dynamic_registration)
+            .unwrap_or_default();
+        self.watch_files.store(watch, Ordering::Relaxed);
+        let pull = params
+            .capabilities
+            .workspace
+            .as_ref()
+            .and_then(|it| it.configuration)
+            .unwrap_or_default();
+        self.pull_configuration.store(pull, Ordering::Relaxed);
+
         Ok(InitializeResult {
             server_info: None,
             offset_encoding: None,
@@ -38,14 +166,29 @@ impl LanguageServer for Backend {
                     TextDocumentSyncKind::FULL,
                 )),
                 code_lens_provider: Some(CodeLensOptions {
-                    resolve_provider: Some(false),
+                    resolve_provider: Some(true),
                 }),
                 document_range_formatting_provider: Some(OneOf::Left(true)),
+                code_action_provider: Some(
+                    CodeActionProviderCapability::Options(CodeActionOptions {
+                        resolve_provider: Some(true),
+                        ..Default::default()
+                    }),
+                ),
+                hover_provider: Some(HoverProviderCapability::Simple(true)),
+                workspace_symbol_provider: Some(OneOf::Left(true)),
+                folding_range_provider: Some(
+                    FoldingRangeProviderCapability::Simple(true),
+                ),
                 execute_command_provider: Some(ExecuteCommandOptions {
                     commands: vec![
                         String::from("lsp_md/searchSimilar"),
+                        String::from("lsp_md/showSimilar"),
                         String::from("lsp_md/keywords"),
                         String::from("lsp_md/findByKeyword"),
+                        String::from("lsp_md/clusters"),
+                        String::from("lsp_md/keywordIndex"),
+                        String::from("lsp_md/backlinks"),
                     ],
                     work_done_progress_options: Default::default(),
                 }),
@@ -58,9 +201,34 @@ impl LanguageServer for Backend {
         self.client
             .log_message(MessageType::INFO, "initialized!")
             .await;
+
+        self.register_file_watcher().await;
+        self.pull_settings().await;
+        // Requests are served while the workspace is crawled and embedded.
+        let state = self.0.clone();
+        tokio::spawn(async move {
+            state.index_workspace().await;
+            state.load_index_cache().await;
+            state.embed_workspace().await;
+            state.refresh_code_lenses().await
+        });
     }
 
     async fn shutdown(&self) -> Result<()> {
+        let Some(cache) = self.index_cache() else {
+            return Ok(());
+        };
+        // The index may be shared with other workspaces, only their own
+        // documents go to this workspace's cache.
+        let saved = self.index.save(&cache, |it| self.workspace.in_roots(it));
+        if let Err(err) = saved {
+            self.client
+                .log_message(
+                    MessageType::ERROR,
+                    format!("failed to save index cache: {:?}", err),
+                )
+                .await;
+        }
         Ok(())
     }
 
@@ -68,33 +236,51 @@ impl LanguageServer for Backend {
         self.client
             .log_message(MessageType::INFO, "file opened!")
             .await;
+        let uri = params.text_document.uri;
         self.on_change(TextDocumentItem {
-            uri: params.text_document.uri,
+            uri: uri.clone(),
             text: params.text_document.text,
             version: params.text_document.version,
         })
-        .await
+        .await;
+        self.0.embed_document(&uri);
+        self.publish_diagnostics(uri).await;
+        self.refresh_code_lenses().await
     }
 
     async fn did_change(&self, mut params: DidChangeTextDocumentParams) {
+        let uri = params.text_document.uri;
         self.on_change(TextDocumentItem {
-            uri: params.text_document.uri,
+            uri: uri.clone(),
             text: std::mem::take(&mut params.content_changes[0].text),
             version: params.text_document.version,
         })
-        .await
+        .await;
+        self.0.embed_document(&uri);
     }
 
-    async fn did_save(&self, _: DidSaveTextDocumentParams) {
+    async fn did_save(&self, params: DidSaveTextDocumentParams) {
         self.client
             .log_message(MessageType::INFO, "file saved!")
             .await;
+        self.0.embed_document(&params.text_document.uri);
+        self.publish_diagnostics(params.text_document.uri).await;
+        self.refresh_code_lenses().await
     }
 
-    async fn did_close(&self, _: DidCloseTextDocumentParams) {
+    async fn did_close(&self, params: DidCloseTextDocumentParams) {
         self.client
             .log_message(MessageType::INFO, "file closed!")
             .await;
+        if let Err(err) = self.workspace.close(&params.text_document.uri) {
+            self.client
+                .log_message(
+                    MessageType::WARNING,
+                    format!("failed to reload closed file: {:?}", err),
+                )
+                .await;
+        }
+        self.0.embed_document(&params.text_document.uri);
     }
 
     async fn code_lens(
@@ -102,49 +288,61 @@ impl LanguageServer for Backend {
         params: CodeLensParams,
     ) -> Result<Option<Vec<CodeLens>>> {
         let uri = params.text_document.uri;
+        let Some(doc) = self.workspace.get(&uri) else {
+            return Ok(None);
+        };
 
-        let entry = self.document_map.get(&uri.to_string()).unwrap();
-        let section_titles = query_section_titles(entry.value());
-
-        let res = section_titles
-            .into_iter()
-            .flat_map(|r| {
-                vec![
-                    CodeLens {
-                        range: r,
-                        command: Some(Command {
-                            title: "Similar docs".to_string(),
-                            command: "lsp_md/searchSimilar".to_string(),
-                            arguments: Some(vec![json!(Location::new(
-                                uri.clone(),
-                                r
-                            ))]),
-                        }),
-                        data: None,
-                    },
-                    CodeLens {
-                        range: r,
-                        command: Some(Command {
-                            title: "Keywords".to_string(),
-                            command: "lsp_md/keywords".to_string(),
-                            arguments: Some(vec![json!(Location::new(
-                                uri.clone(),
-                                r
-                            ))]),
-                        }),
-                        data: None,
-                    },
-                ]
-            })
-            .collect();
+        Ok(Some(code_lenses(&uri, doc.value())))
+    }
 
-        Ok(Some(res))
+    async fn code_lens_resolve(&self, params: CodeLens) -> Result<CodeLens> {
+        let resolved = match self.encoder().await {
+            Ok(enc) => resolve_code_lens(
+                &self.workspace,
+                &self.index,
+                &self.keywords,
+                enc,
+                params,
+            ),
+            Err(err) => Err(err),
+        };
+        match resolved {
+            Ok(it) => Ok(it),
+            Err(err) => {
+                self.client
+                    .log_message(
+                        MessageType::ERROR,
+                        format!("code lens resolve failed: {:?}", err),
+                    )
+                    .await;
+                Err(Error::internal_error())
+            },
+        }
     }
 
-    async fn did_change_configuration(&self, _: DidChangeConfigurationParams) {
+    async fn did_change_configuration(
+        &self,
+        params: DidChangeConfigurationParams,
+    ) {
         self.client
             .log_message(MessageType::INFO, "configuration changed!")
             .await;
+        match client_layer(params.settings) {
+            Ok(it) => self.settings_layers.lock().unwrap().client = it,
+            Err(err) => {
+                self.client
+                    .show_message(
+                        MessageType::ERROR,
+                        format!("invalid settings: {:#}", err),
+                    )
+                    .await
+            },
+        }
+        self.reload_config_file().await;
+        self.pull_settings().await;
+        if self.apply_settings().await {
+            self.refresh_diagnostics().await;
+        }
     }
 
     async fn did_change_workspace_folders(
@@ -156,10 +354,65 @@ impl LanguageServer for Backend {
             .await;
     }
 
-    async fn did_change_watched_files(&self, _: DidChangeWatchedFilesParams) {
+    async fn did_change_watched_files(
+        &self,
+        params: DidChangeWatchedFilesParams,
+    ) {
         self.client
             .log_message(MessageType::INFO, "watched files have changed!")
             .await;
+        let mut changed = Vec::new();
+        let mut config_changed = false;
+        for event in params.changes {
+            let uri = event.uri;
+            if uri.path().ends_with(&format!("/{}", CONFIG_FILE)) {
+                config_changed = true;
+                continue;
+            }
+            if event.typ == FileChangeType::DELETED {
+                self.workspace.remove(&uri);
+                if self.workspace.get(&uri).is_none() {
+                    self.index.remove(&uri);
+                    self.keywords.remove(&uri);
+                    self.client
+                        .publish_diagnostics(uri, Vec::new(), None)
+                        .await;
+                }
+                continue;
+            }
+            let Ok(path) = uri.to_file_path() else {
+                continue;
+            };
+            match self.workspace.index_file(&path) {
+                Ok(()) => changed.push(uri),
+                Err(err) => {
+                    self.client
+                        .log_message(
+                            MessageType::WARNING,
+                            format!("failed to index {:?}: {:?}", path, err),
+                        )
+                        .await
+                },
+            }
+        }
+
+        if !changed.is_empty() {
+            if let Err(err) = self.encoder().await {
+                self.client
+                    .log_message(MessageType::ERROR, format!("{:?}", err))
+                    .await;
+            }
+        }
+        for uri in &changed {
+            self.0.embed_document(uri);
+        }
+        if config_changed {
+            self.reload_config_file().await;
+            self.apply_settings().await;
+        }
+        // Other files changing can break links or add near-duplicates of open
+        // ones.
+        self.refresh_diagnostics().await
     }
 
     async fn execute_command(
@@ -168,39 +421,72 @@ impl LanguageServer for Backend {
     ) -> Result<Option<Value>> {
         match params.command.as_str() {
             "lsp_md/searchSimilar" => {
-                let loc: Location =
-                    serde_json::from_value(params.arguments[0].to_owned())
-                        .unwrap();
-                Ok(Some(json!(find_similar(
-                    loc.uri.clone(),
-                    self.document_map.get(loc.uri.as_str()).unwrap().value(),
-                    &self.encoder,
-                    &loc.range.start
-                ))))
+                let resp = self.search_similar(&params.arguments).await;
+                self.respond(resp).await
+            },
+            "lsp_md/showSimilar" => {
+                match self.search_similar(&params.arguments).await {
+                    Ok(it) => self.show_results(it).await,
+                    Err(err) => self.respond::<()>(Err(err)).await,
+                }
             },
             "lsp_md/keywords" => {
-                let loc: Location =
-                    serde_json::from_value(params.arguments[0].to_owned())
-                        .unwrap();
-                Ok(Some(json!(extract_keywords(
-                    self.document_map.get(loc.uri.as_str()).unwrap().value(),
-                    &self.encoder,
-                    &loc.range.start,
-                )
-                .unwrap())))
+                let resp = async {
+                    let loc: Location = argument(&params.arguments, 0)?;
+                    let enc = self.encoder().await?;
+                    let doc =
+                        self.workspace.get(&loc.uri).ok_or_else(|| {
+                            anyhow::anyhow!("unknown document: {}", loc.uri)
+                        })?;
+                    extract_keywords(
+                        &self.keywords,
+                        &loc.uri,
+                        doc.value(),
+                        enc,
+                        &loc.range.start,
+                    )
+                }
+                .await;
+                self.respond(resp).await
             },
             "lsp_md/findByKeyword" => {
-                let query: KeywordQuery =
-                    serde_json::from_value(params.arguments[0].to_owned())
-                        .unwrap();
-                let doc = self.document_map.get(query.uri.as_str()).unwrap();
-                let resp = find_by_keyword(
-                    query.uri,
-                    &self.encoder,
-                    doc.value(),
-                    &query.keyword,
-                );
-                Ok(Some(json!(resp)))
+                let resp = async {
+                    let query: KeywordQuery = argument(&params.arguments, 0)?;
+                    let enc = self.encoder().await?;
+                    find_by_keyword(
+                        &self.workspace,
+                        &self.index,
+                        enc,
+                        &query.uri,
+                        &query.keyword,
+                        &query.options,
+                    )
+                }
+                .await;
+                self.respond(resp).await
+            },
+            "lsp_md/backlinks" => {
+                let resp = argument(&params.arguments, 0)
+                    .and_then(|loc| section_backlinks(&self.workspace, &loc));
+                self.respond(resp).await
+            },
+            "lsp_md/keywordIndex" => {
+                let resp = match self.encoder().await {
+                    Ok(enc) => self.keywords.entries(&self.workspace, enc),
+                    Err(err) => Err(err),
+                };
+                self.respond(resp).await
+            },
+            "lsp_md/clusters" => {
+                let resp = async {
+                    let opts: ClusterOptions =
+                        optional_argument(&params.arguments, 0)?
+                            .unwrap_or_default();
+                    let enc = self.encoder().await?;
+                    clusters(&self.workspace, &self.index, enc, &opts)
+                }
+                .await;
+                self.respond(resp).await
             },
             _ => {
                 self.client
@@ -215,35 +501,854 @@ impl LanguageServer for Backend {
         &self,
         params: DocumentRangeFormattingParams,
     ) -> Result<Option<Vec<TextEdit>>> {
-        let uri = params.text_document.uri.to_string();
-        let Some(doc) = self.document_map.get(&uri) else {
+        let Some(doc) = self.workspace.get(&params.text_document.uri) else {
+            return Ok(None);
+        };
+
+        let width = self.settings.lock().unwrap().wrap_width;
+        Ok(CodeFormatter::new(doc.value())
+            .wrap_width(width)
+            .format(params.range))
+    }
+
+    async fn code_action(
+        &self,
+        params: CodeActionParams,
+    ) -> Result<Option<CodeActionResponse>> {
+        let uri = params.text_document.uri;
+        let mut res: CodeActionResponse = params
+            .context
+            .diagnostics
+            .iter()
+            .filter_map(|it| merge_action(&self.workspace, &uri, it))
+            .map(CodeActionOrCommand::CodeAction)
+            .collect();
+        res.extend(
+            tag_action(&self.workspace, &uri, &params.range.start)
+                .map(CodeActionOrCommand::CodeAction),
+        );
+
+        Ok(Some(res))
+    }
+
+    async fn code_action_resolve(
+        &self,
+        params: CodeAction,
+    ) -> Result<CodeAction> {
+        let resolved = match self.encoder().await {
+            Ok(enc) => resolve_tag_action(
+                &self.workspace,
+                &self.keywords,
+                &self.tags,
+                enc,
+                params,
+            ),
+            Err(err) => Err(err),
+        };
+        match resolved {
+            Ok(it) => Ok(it),
+            Err(err) => {
+                self.client
+                    .log_message(
+                        MessageType::ERROR,
+                        format!("tag suggestion failed: {:?}", err),
+                    )
+                    .await;
+                Err(Error::internal_error())
+            },
+        }
+    }
+
+    async fn folding_range(
+        &self,
+        params: FoldingRangeParams,
+    ) -> Result<Option<Vec<FoldingRange>>> {
+        let Some(doc) = self.workspace.get(&params.text_document.uri) else {
             return Ok(None);
         };
 
-        Ok(CodeFormatter::new(doc.value()).format(params.range))
+        Ok(Some(folding_ranges(doc.value())))
+    }
+
+    async fn hover(&self, params: HoverParams) -> Result<Option<Hover>> {
+        let params = params.text_document_position_params;
+        let uri = params.text_document.uri;
+        let Some(doc) = self.workspace.get(&uri) else {
+            return Ok(None);
+        };
+
+        Ok(hover(
+            &uri,
+            doc.value(),
+            &self.workspace,
+            &self.keywords,
+            &params.position,
+        ))
+    }
+
+    async fn symbol(
+        &self,
+        params: WorkspaceSymbolParams,
+    ) -> Result<Option<Vec<SymbolInformation>>> {
+        // Without the model only fuzzy matches are listed.
+        match workspace_symbols(
+            &self.workspace,
+            &self.index,
+            self.loaded_encoder(),
+            &params.query,
+        ) {
+            Ok(res) => Ok(Some(res)),
+            Err(err) => {
+                self.client
+                    .log_message(
+                        MessageType::ERROR,
+                        format!("workspace symbol failed: {:?}", err),
+                    )
+                    .await;
+                Err(Error::internal_error())
+            },
+        }
     }
 }
 
 struct TextDocumentItem {
     uri: Url,
     text: String,
-    #[allow(dead_code)]
     version: i32,
 }
 
 impl Backend {
     pub fn new(client: Client) -> Self {
-        Backend {
+        Self::with_shared(client, Shared::default())
+    }
+
+    /// A backend using the model and indexes of other connections.
+    pub fn with_shared(client: Client, shared: Shared) -> Self {
+        Backend(Arc::new(State {
             client,
-            encoder: Mutex::new(BertModel::default()),
-            document_map: DashMap::new(),
+            encoder: shared.encoder,
+            workspace: Workspace::default(),
+            index: shared.index,
+            keywords: shared.keywords,
+            tags: shared.tags,
+            settings: Mutex::new(Settings::default()),
+            settings_layers: Mutex::new(SettingsLayers::default()),
+            pull_configuration: AtomicBool::new(false),
+            code_lens_refresh: AtomicBool::new(false),
+            watch_files: AtomicBool::new(false),
+            work_done_progress: AtomicBool::new(false),
+            show_document: AtomicBool::new(false),
+            progress: DashMap::new(),
+            next_progress: AtomicU64::new(0),
+            embed_requests: DashMap::new(),
+        }))
+    }
+
+    /// Handle `window/workDoneProgress/cancel` for a running report.
+    pub async fn work_done_progress_cancel(
+        &self,
+        params: WorkDoneProgressCancelParams,
+    ) {
+        if let Some(it) = self.progress.get(&token_key(&params.token)) {
+            it.store(true, Ordering::Relaxed);
         }
     }
+}
 
-    async fn on_change(&self, params: TextDocumentItem) {
-        self.document_map.insert(
-            params.uri.to_string(),
-            Document::parse(&params.text).unwrap(),
+impl Deref for Backend {
+    type Target = State;
+
+    fn deref(&self) -> &State {
+        &self.0
+    }
+}
+
+impl State {
+    /// Re-read `.lsp-md.toml` from the workspace roots.
+    async fn reload_config_file(&self) {
+        match read_config_file(&self.workspace.roots()) {
+            Ok(it) => self.settings_layers.lock().unwrap().file = it,
+            Err(err) => {
+                self.client
+                    .show_message(MessageType::ERROR, format!("{:#}", err))
+                    .await
+            },
+        }
+    }
+
+    /// Request the `lsp-md` section with `workspace/configuration`, when the
+    /// client supports it.
+    async fn pull_settings(&self) {
+        if !self.pull_configuration.load(Ordering::Relaxed) {
+            return;
+        }
+        let items = vec![ConfigurationItem {
+            scope_uri: None,
+            section: Some(CONFIG_SECTION.to_string()),
+        }];
+        let pulled = match self.client.configuration(items).await {
+            Ok(mut it) if !it.is_empty() => client_layer(it.swap_remove(0)),
+            Ok(_) => return,
+            Err(err) => {
+                self.client
+                    .log_message(
+                        MessageType::WARNING,
+                        format!("workspace/configuration failed: {:?}", err),
+                    )
+                    .await;
+                return;
+            },
+        };
+        match pulled {
+            Ok(it) => self.settings_layers.lock().unwrap().client = it,
+            Err(err) => {
+                self.client
+                    .show_message(
+                        MessageType::ERROR,
+                        format!("invalid settings: {:#}", err),
+                    )
+                    .await
+            },
+        }
+    }
+
+    /// Merge the settings layers. Unknown keys are logged and ignored, invalid
+    /// settings are reported with `window/showMessage` and the previous
+    /// settings kept. Returns whether
+    /// the settings changed.
+    async fn apply_settings(&self) -> bool {
+        let (merged, unknown) = {
+            let layers = self.settings_layers.lock().unwrap();
+            (layers.settings(), layers.unknown_keys())
+        };
+        if !unknown.is_empty() {
+            self.client
+                .log_message(
+                    MessageType::WARNING,
+                    format!(
+                        "ignoring unknown settings: {}",
+                        unknown.join(", ")
+                    ),
+                )
+                .await;
+        }
+        let settings = match merged {
+            Ok(it) => it,
+            Err(err) => {
+                self.client
+                    .show_message(
+                        MessageType::ERROR,
+                        format!("invalid settings: {:#}", err),
+                    )
+                    .await;
+                return false;
+            },
+        };
+        let previous = std::mem::replace(
+            &mut *self.settings.lock().unwrap(),
+            settings.clone(),
         );
+        if previous.heading_pattern != settings.heading_pattern {
+            // Validated with the other settings.
+            self.workspace
+                .set_headings(settings.headings().ok().flatten());
+        }
+        if self.encoder.initialized() && previous.model != settings.model {
+            self.client
+                .show_message(
+                    MessageType::INFO,
+                    "The model change takes effect after a restart",
+                )
+                .await;
+        }
+        previous != settings
+    }
+
+    /// Re-publish diagnostics of open documents and refresh lenses.
+    async fn refresh_diagnostics(&self) {
+        for uri in self.workspace.open_uris() {
+            self.publish_diagnostics(uri).await;
+        }
+        self.refresh_code_lenses().await
+    }
+
+    /// Lint the document and flag sections that nearly duplicate others.
+    async fn publish_diagnostics(&self, uri: Url) {
+        // Tag diagnostics with the version they were computed from, so
+        // clients drop them if the buffer changed in the meantime.
+        let version = self.workspace.version(&uri);
+        let mut diags = lint(&self.workspace, &uri);
+        let threshold = self.settings.lock().unwrap().duplicate_threshold;
+        // Duplicates are left out until the model has loaded.
+        let duplicates = self.loaded_encoder().map(|enc| {
+            duplicate_diagnostics(
+                &self.workspace,
+                &self.index,
+                enc,
+                &uri,
+                threshold,
+            )
+        });
+        match duplicates {
+            Some(Ok(it)) => diags.extend(it),
+            Some(Err(err)) => {
+                self.client
+                    .log_message(
+                        MessageType::ERROR,
+                        format!("duplicate detection failed: {:?}", err),
+                    )
+                    .await
+            },
+            None => {},
+        }
+        self.client.publish_diagnostics(uri, diags, version).await
+    }
+
+    /// Similar notes for a location and optional search options.
+    async fn search_similar(
+        &self,
+        args: &[Value],
+    ) -> anyhow::Result<Vec<ScoredLocation<'static>>> {
+        let loc: Location = argument(args, 0)?;
+        let opts = match optional_argument(args, 1)? {
+            Some(it) => it,
+            None => SearchOptions {
+                limit: Some(self.settings.lock().unwrap().similar_limit),
+                ..Default::default()
+            },
+        };
+        let enc = self.encoder().await?;
+        find_similar(&self.workspace, &self.index, enc, &loc, &opts)
+    }
+
+    /// Let the user pick a result with `window/showMessageRequest` and open
+    /// it with `window/showDocument`, or name it in a message for clients
+    /// that cannot show documents. Returns the chosen location.
+    async fn show_results(
+        &self,
+        results: Vec<ScoredLocation<'static>>,
+    ) -> Result<Option<Value>> {
+        if results.is_empty() {
+            self.client
+                .show_message(MessageType::INFO, "No similar notes found")
+                .await;
+            return Ok(None);
+        }
+        let items: Vec<MessageActionItem> = results
+            .iter()
+            .enumerate()
+            .map(|(i, it)| MessageActionItem {
+                title: format!("{}. {}", i + 1, it.label()),
+                properties: Default::default(),
+            })
+            .collect();
+        let picked = self
+            .client
+            .show_message_request(
+                MessageType::INFO,
+                "Similar notes",
+                Some(items.clone()),
+            )
+            .await?;
+        let Some(index) = picked
+            .and_then(|it| items.iter().position(|v| v.title == it.title))
+        else {
+            return Ok(None);
+        };
+
+        let location = results[index].location.clone();
+        if !self.show_document.load(Ordering::Relaxed) {
+            let path = location
+                .uri
+                .to_file_path()
+                .map(|it| it.display().to_string())
+                .unwrap_or_else(|_| location.uri.to_string());
+            self.client
+                .show_message(
+                    MessageType::INFO,
+                    format!("{}:{}", path, location.range.start.line + 1),
+                )
+                .await;
+            return Ok(Some(json!(location)));
+        }
+        let shown = self
+            .client
+            .show_document(ShowDocumentParams {
+                uri: location.uri.clone(),
+                external: Some(false),
+                take_focus: Some(true),
+                selection: Some(location.range),
+            })
+            .await?;
+        if !shown {
+            self.client
+                .log_message(
+                    MessageType::WARNING,
+                    format!("client did not open {}", location.uri),
+                )
+                .await;
+        }
+        Ok(Some(json!(location)))
+    }
+
+    /// The sentence model. The first call loads it off the async runtime,
+    /// later calls wait until it is ready. A failed load is retried by the
+    /// next call.
+    async fn encoder(&self) -> anyhow::Result<&Mutex<BertModel>> {
+        self.encoder
+            .get_or_try_init(|| async {
+                let progress =
+                    self.begin_progress("Loading model", false).await;
+                let model_type = self.settings.lock().unwrap().model;
+                let loaded = tokio::task::spawn_blocking(move || {
+                    BertModel::new(model_type)
+                })
+                .await;
+                let model = match loaded {
+                    Ok(it) => it,
+                    Err(err) => Err(err.into()),
+                }
+                .context("model failed to load");
+                let message = match model {
+                    Ok(_) => "model loaded",
+                    Err(_) => "model failed to load",
+                };
+                progress.end(message.to_string()).await;
+                Ok(LoadedModel {
+                    model_type,
+                    model: Mutex::new(model?),
+                })
+            })
+            .await
+            .map(|it| &it.model)
+    }
+
+    /// The sentence model if it has loaded.
+    fn loaded_encoder(&self) -> Option<&Mutex<BertModel>> {
+        self.encoder.get().map(|it| &it.model)
+    }
+
+    /// Start a progress report. Reports are dropped silently when the client
+    /// does not support work done progress.
+    async fn begin_progress(
+        &self,
+        title: &str,
+        cancellable: bool,
+    ) -> Progress<'_> {
+        let token = NumberOrString::String(format!(
+            "lsp-md/{}",
+            self.next_progress.fetch_add(1, Ordering::Relaxed)
+        ));
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let mut progress = Progress {
+            backend: self,
+            token: None,
+            cancellable,
+            cancelled: cancelled.clone(),
+        };
+        if !self.work_done_progress.load(Ordering::Relaxed) {
+            return progress;
+        }
+        let created = self
+            .client
+            .send_request::<WorkDoneProgressCreate>(
+                WorkDoneProgressCreateParams {
+                    token: token.clone(),
+                },
+            )
+            .await;
+        if created.is_err() {
+            return progress;
+        }
+        self.progress.insert(token_key(&token), cancelled);
+        progress.token = Some(token);
+        progress
+            .send(WorkDoneProgress::Begin(WorkDoneProgressBegin {
+                title: title.to_string(),
+                cancellable: Some(cancellable),
+                message: None,
+                percentage: Some(0),
+            }))
+            .await;
+        progress
+    }
+
+    /// Parse every markdown file under the workspace roots.
+    async fn index_workspace(&self) {
+        let (files, errors) = self.workspace.files();
+        for (path, err) in errors {
+            self.client
+                .log_message(
+                    MessageType::WARNING,
+                    format!("skipped {:?}: {}", path, err),
+                )
+                .await
+        }
+        let progress = self.begin_progress("Indexing notes", true).await;
+        let started = Instant::now();
+        let mut count = 0;
+        for (i, path) in files.iter().enumerate() {
+            if progress.is_cancelled() {
+                break;
+            }
+            match self.workspace.index_file(path) {
+                Ok(()) => count += 1,
+                Err(err) => {
+                    self.client
+                        .log_message(
+                            MessageType::WARNING,
+                            format!("failed to index {:?}: {:?}", path, err),
+                        )
+                        .await
+                },
+            }
+            progress
+                .report(
+                    format!(
+                        "{}/{} files{}",
+                        i + 1,
+                        files.len(),
+                        eta(started.elapsed(), i + 1, files.len())
+                    ),
+                    i + 1,
+                    files.len(),
+                )
+                .await;
+        }
+        progress
+            .end(format!("indexed {} markdown files", count))
+            .await;
+        self.client
+            .log_message(
+                MessageType::INFO,
+                format!("indexed {} markdown files", count),
+            )
+            .await
+    }
+
+    /// Load the index cache, unless a warm index is shared with other
+    /// connections, which is newer.
+    async fn load_index_cache(&self) {
+        let cache = self.index_cache().filter(|it| it.exists());
+        if let Some(cache) = cache {
+            if let Err(err) = self.index.load(&cache) {
+                self.client
+                    .log_message(
+                        MessageType::WARNING,
+                        format!("ignoring index cache {:?}: {:?}", cache, err),
+                    )
+                    .await;
+            }
+        }
+    }
+
+    /// Embed the sections of every document, so the first search does not
+    /// pay for the whole workspace.
+    async fn embed_workspace(self: &Arc<Self>) {
+        let uris = self.workspace.uris();
+        if let Err(err) = self.encoder().await {
+            self.client
+                .log_message(MessageType::ERROR, format!("{:?}", err))
+                .await;
+            return;
+        }
+        let progress = self.begin_progress("Embedding notes", true).await;
+        let started = Instant::now();
+        let mut sections = 0;
+        for (i, uri) in uris.iter().enumerate() {
+            if progress.is_cancelled() {
+                progress.end("cancelled".to_string()).await;
+                return;
+            }
+            // Encoding is CPU bound, keep it off the async workers.
+            let state = self.clone();
+            let key = uri.clone();
+            let joined =
+                tokio::task::spawn_blocking(move || -> anyhow::Result<_> {
+                    let (Some(enc), Some(doc)) =
+                        (state.loaded_encoder(), state.workspace.get(&key))
+                    else {
+                        return Ok(0);
+                    };
+                    Ok(state.index.embed(&key, doc.value(), enc)?.len())
+                })
+                .await;
+            let embedded = match joined {
+                Ok(it) => it,
+                Err(err) => Err(err.into()),
+            };
+            match embedded {
+                Ok(it) => sections += it,
+                Err(err) => {
+                    self.client
+                        .log_message(
+                            MessageType::WARNING,
+                            format!("failed to embed {}: {:?}", uri, err),
+                        )
+                        .await
+                },
+            }
+            progress
+                .report(
+                    format!(
+                        "{}/{} files, {} sections{}",
+                        i + 1,
+                        uris.len(),
+                        sections,
+                        eta(started.elapsed(), i + 1, uris.len())
+                    ),
+                    i + 1,
+                    uris.len(),
+                )
+                .await;
+        }
+        progress
+            .end(format!("embedded {} sections", sections))
+            .await
+    }
+
+    /// Ask the client to report changes of markdown files on disk.
+    async fn register_file_watcher(&self) {
+        if !self.watch_files.load(Ordering::Relaxed) {
+            return;
+        }
+        let options = DidChangeWatchedFilesRegistrationOptions {
+            watchers: vec![
+                FileSystemWatcher {
+                    glob_pattern: GlobPattern::String("**/*.md".to_string()),
+                    kind: None,
+                },
+                FileSystemWatcher {
+                    glob_pattern: GlobPattern::String(format!(
+                        "**/{}",
+                        CONFIG_FILE
+                    )),
+                    kind: None,
+                },
+            ],
+        };
+        let registration = Registration {
+            id: "lsp-md/watch".to_string(),
+            method: "workspace/didChangeWatchedFiles".to_string(),
+            register_options: Some(json!(options)),
+        };
+        if let Err(err) =
+            self.client.register_capability(vec![registration]).await
+        {
+            self.client
+                .log_message(
+                    MessageType::WARNING,
+                    format!("failed to watch files: {:?}", err),
+                )
+                .await;
+        }
+    }
+
+    /// Ask the client to re-request code lenses after the index changed.
+    async fn refresh_code_lenses(&self) {
+        if !self.code_lens_refresh.load(Ordering::Relaxed) {
+            return;
+        }
+        if let Err(err) = self.client.code_lens_refresh().await {
+            self.client
+                .log_message(
+                    MessageType::WARNING,
+                    format!("code lens refresh failed: {:?}", err),
+                )
+                .await;
+        }
+    }
+
+    /// Section index cache for the current workspace roots and the loaded
+    /// model, or the configured one until it is loaded. A model setting
+    /// changed after loading only applies after a restart.
+    fn index_cache(&self) -> Option<PathBuf> {
+        let model = match self.encoder.get() {
+            Some(it) => it.model_type,
+            None => self.settings.lock().unwrap().model,
+        };
+        SectionIndex::cache_path(&self.workspace.roots(), model)
+    }
+
+    /// Serialize a command result, reporting failures to the client.
+    async fn respond<T: Serialize>(
+        &self,
+        resp: anyhow::Result<T>,
+    ) -> Result<Option<Value>> {
+        match resp {
+            Ok(it) => Ok(Some(json!(it))),
+            Err(err) => {
+                self.client
+                    .log_message(
+                        MessageType::ERROR,
+                        format!("command failed: {:?}", err),
+                    )
+                    .await;
+                Err(Error::internal_error())
+            },
+        }
+    }
+
+    /// Re-embed the sections of a changed document in the background, once
+    /// the model is loaded and edits pause for `EMBED_DEBOUNCE`. Searches
+    /// rank against the last indexed state in the meantime.
+    fn embed_document(self: &Arc<Self>, uri: &Url) {
+        if self.loaded_encoder().is_none() {
+            return;
+        }
+        let request = {
+            let mut it =
+                self.embed_requests.entry(uri.to_string()).or_default();
+            *it += 1;
+            *it
+        };
+        let state = self.clone();
+        let uri = uri.clone();
+        tokio::spawn(async move {
+            tokio::time::sleep(EMBED_DEBOUNCE).await;
+            let latest = state.embed_requests.get(uri.as_str()).map(|it| *it);
+            if latest != Some(request) {
+                return;
+            }
+            // Encoding is CPU bound, keep it off the async workers. The text
+            // is parsed again so edits do not wait for the encoder.
+            let blocking = state.clone();
+            let key = uri.clone();
+            let joined = tokio::task::spawn_blocking(move || {
+                let (Some(enc), Some(text)) =
+                    (blocking.loaded_encoder(), blocking.workspace.text(&key))
+                else {
+                    return Ok(());
+                };
+                let doc = blocking.workspace.parse(&text)?;
+                blocking.index.embed(&key, &doc, enc).map(|_| ())
+            })
+            .await;
+            state
+                .embed_requests
+                .remove_if(uri.as_str(), |_, it| *it == request);
+            let embedded = match joined {
+                Ok(it) => it,
+                Err(err) => Err(err.into()),
+            };
+            if let Err(err) = embedded {
+                state
+                    .client
+                    .log_message(
+                        MessageType::WARNING,
+                        format!("failed to embed {}: {:?}", uri, err),
+                    )
+                    .await;
+            }
+        });
+    }
+
+    async fn on_change(&self, params: TextDocumentItem) {
+        let doc = self.workspace.parse(&params.text).unwrap();
+        if !self.workspace.update(&params.uri, params.version, doc) {
+            self.client
+                .log_message(
+                    MessageType::WARNING,
+                    format!(
+                        "ignoring out-of-order version {} of {}",
+                        params.version, params.uri
+                    ),
+                )
+                .await;
+        }
+    }
+}
+
+/// A running `$/progress` report, ended by `end`.
+struct Progress<'a> {
+    backend: &'a State,
+    token: Option<NumberOrString>,
+    cancellable: bool,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Progress<'_> {
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+    }
+
+    /// Report `done` out of `total` steps. Also yields, so a cancel request
+    /// can be handled between steps.
+    async fn report(&self, message: String, done: usize, total: usize) {
+        let percentage = (done * 100 / total.max(1)) as u32;
+        self.send(WorkDoneProgress::Report(WorkDoneProgressReport {
+            cancellable: Some(self.cancellable),
+            message: Some(message),
+            percentage: Some(percentage),
+        }))
+        .await;
+        tokio::task::yield_now().await;
+    }
+
+    async fn end(self, message: String) {
+        self.send(WorkDoneProgress::End(WorkDoneProgressEnd {
+            message: Some(message),
+        }))
+        .await;
+        if let Some(token) = &self.token {
+            self.backend.progress.remove(&token_key(token));
+        }
+    }
+
+    async fn send(&self, value: WorkDoneProgress) {
+        let Some(token) = self.token.clone() else {
+            return;
+        };
+        self.backend
+            .client
+            .send_notification::<ProgressNotification>(ProgressParams {
+                token,
+                value: ProgressParamsValue::WorkDone(value),
+            })
+            .await;
+    }
+}
+
+/// The command argument at `index`.
+fn argument<T: DeserializeOwned>(
+    args: &[Value],
+    index: usize,
+) -> anyhow::Result<T> {
+    optional_argument(args, index)?
+        .ok_or_else(|| anyhow::anyhow!("missing argument {}", index + 1))
+}
+
+/// The command argument at `index`, if given.
+fn optional_argument<T: DeserializeOwned>(
+    args: &[Value],
+    index: usize,
+) -> anyhow::Result<Option<T>> {
+    args.get(index)
+        .map(|it| {
+            serde_json::from_value(it.to_owned())
+                .with_context(|| format!("invalid argument {}", index + 1))
+        })
+        .transpose()
+}
+
+fn token_key(token: &NumberOrString) -> String {
+    match token {
+        NumberOrString::Number(it) => it.to_string(),
+        NumberOrString::String(it) => it.clone(),
+    }
+}
+
+/// `, ETA 1m 5s` from the pace so far, empty until there is a pace or once
+/// done.
+fn eta(elapsed: Duration, done: usize, total: usize) -> String {
+    if done == 0 || done >= total {
+        return String::new();
+    }
+    let secs = (elapsed.as_secs_f64() / done as f64 * (total - done) as f64)
+        .round() as u64;
+    if secs >= 60 {
+        format!(", ETA {}m {}s", secs / 60, secs % 60)
+    } else {
+        format!(", ETA {}s", secs)
     }
 }
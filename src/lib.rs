@@ -1,4 +1,7 @@
+mod cli;
 mod document;
 mod language_server;
+mod settings;
 
-pub use language_server::Backend;
+pub use cli::{Cli, Listen};
+pub use language_server::{Backend, Shared};
@@ -1,17 +1,86 @@
-use lsp_md::Backend;
+use std::process::ExitCode;
+
+use clap::Parser;
+use lsp_md::{Backend, Cli, Listen, Shared};
+use tokio::io::{AsyncRead, AsyncWrite};
 use tower_lsp::{LspService, Server};
 
-#[tokio::main]
-async fn main() -> anyhow::Result<()> {
-    let stdin = tokio::io::stdin();
-    let stdout = tokio::io::stdout();
+fn main() -> anyhow::Result<ExitCode> {
+    let cli = Cli::parse();
+    // Commands run outside the async runtime, loading the model blocks.
+    if let Some(command) = cli.command {
+        return command.run();
+    }
+
+    let runtime = tokio::runtime::Runtime::new()?;
+    match cli.listen {
+        None => runtime.block_on(serve(
+            tokio::io::stdin(),
+            tokio::io::stdout(),
+            Shared::default(),
+        )),
+        Some(Listen::Tcp(addr)) => runtime.block_on(listen_tcp(&addr))?,
+        #[cfg(unix)]
+        Some(Listen::Unix(path)) => runtime.block_on(listen_unix(&path))?,
+        #[cfg(not(unix))]
+        Some(Listen::Unix(_)) => {
+            anyhow::bail!("unix sockets are not supported on this platform")
+        },
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Serve one client until it exits.
+async fn serve<I, O>(input: I, output: O, shared: Shared)
+where
+    I: AsyncRead + Unpin,
+    O: AsyncWrite,
+{
+    let (service, socket) =
+        LspService::build(|client| Backend::with_shared(client, shared))
+            .custom_method(
+                "window/workDoneProgress/cancel",
+                Backend::work_done_progress_cancel,
+            )
+            .finish();
+
+    Server::new(input, output, socket).serve(service).await;
+}
 
-    let (service, socket) = tokio::task::spawn_blocking(|| {
-        LspService::build(|client| Backend::new(client)).finish()
-    })
-    .await?;
+async fn listen_tcp(addr: &str) -> anyhow::Result<()> {
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    eprintln!("listening on tcp:{}", listener.local_addr()?);
+    let shared = Shared::default();
+    loop {
+        let (stream, peer) = listener.accept().await?;
+        eprintln!("client connected from {}", peer);
+        let (input, output) = tokio::io::split(stream);
+        tokio::spawn(serve(input, output, shared.clone()));
+    }
+}
 
-    Server::new(stdin, stdout, socket).serve(service).await;
+/// A stale socket left by a previous server is replaced, anything else at the
+/// path is an error.
+#[cfg(unix)]
+async fn listen_unix(path: &std::path::Path) -> anyhow::Result<()> {
+    use std::os::unix::fs::FileTypeExt;
 
-    Ok(())
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        let stale = metadata.file_type().is_socket() &&
+            std::os::unix::net::UnixStream::connect(path).is_err();
+        if !stale {
+            anyhow::bail!("address in use: {}", path.display());
+        }
+        std::fs::remove_file(path)?;
+    }
+    let listener = tokio::net::UnixListener::bind(path)?;
+    eprintln!("listening on unix:{}", path.display());
+    let shared = Shared::default();
+    loop {
+        let (stream, _) = listener.accept().await?;
+        eprintln!("client connected");
+        let (input, output) = tokio::io::split(stream);
+        tokio::spawn(serve(input, output, shared.clone()));
+    }
 }
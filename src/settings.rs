@@ -0,0 +1,225 @@
+use std::path::Path;
+
+use anyhow::Context;
+use regex::{Regex, RegexBuilder};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+
+use crate::document::{ModelType, DEFAULT_WRAP_WIDTH};
+
+/// Project configuration file, read from the workspace root.
+pub const CONFIG_FILE: &str = ".lsp-md.toml";
+/// Section requested with `workspace/configuration`.
+pub const CONFIG_SECTION: &str = "lsp-md";
+
+/// Server settings. Every source uses the same camelCase keys.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase", default)]
+pub struct Settings {
+    /// Sections at least this similar to another are flagged as duplicates.
+    pub duplicate_threshold: f32,
+    /// Line width of range formatting.
+    pub wrap_width: usize,
+    /// Results of `lsp_md/searchSimilar` unless a limit is given.
+    pub similar_limit: usize,
+    /// Sentence model, changes apply after a restart.
+    pub model: ModelType,
+    /// Lines matching this regex start sections, titled by its first capture
+    /// group, instead of markdown headings.
+    pub heading_pattern: Option<String>,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            duplicate_threshold: 0.95,
+            wrap_width: DEFAULT_WRAP_WIDTH,
+            similar_limit: 10,
+            model: ModelType::default(),
+            heading_pattern: None,
+        }
+    }
+}
+
+impl Settings {
+    /// The compiled heading pattern, matched line by line.
+    pub fn headings(&self) -> anyhow::Result<Option<Regex>> {
+        let Some(pattern) = &self.heading_pattern else {
+            return Ok(None);
+        };
+        Ok(Some(RegexBuilder::new(pattern).multi_line(true).build()?))
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        let mut errors = Vec::new();
+        if !(0.0..=1.0).contains(&self.duplicate_threshold) {
+            errors.push("duplicateThreshold must be between 0 and 1");
+        }
+        if self.wrap_width < 20 {
+            errors.push("wrapWidth must be at least 20");
+        }
+        if self.similar_limit == 0 {
+            errors.push("similarLimit must be at least 1");
+        }
+        match self.headings() {
+            Ok(Some(re)) if re.is_match("") => {
+                errors.push("headingPattern must not match empty lines")
+            },
+            Ok(_) => {},
+            Err(_) => errors.push("headingPattern must be a valid regex"),
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow::anyhow!(errors.join(", ")))
+        }
+    }
+}
+
+/// Settings as given by each source. Later sources override earlier ones key
+/// by key.
+#[derive(Debug, Default)]
+pub struct SettingsLayers {
+    /// `.lsp-md.toml` at the workspace root.
+    pub file: Map<String, Value>,
+    /// `initializationOptions`.
+    pub initialization: Map<String, Value>,
+    /// `workspace/configuration`, or settings pushed with
+    /// `workspace/didChangeConfiguration`.
+    pub client: Map<String, Value>,
+}
+
+impl SettingsLayers {
+    /// The merged settings, or why they are invalid.
+    pub fn settings(&self) -> anyhow::Result<Settings> {
+        let mut merged = Map::new();
+        for layer in [&self.file, &self.initialization, &self.client] {
+            merged.extend(layer.clone());
+        }
+        let settings: Settings = serde_json::from_value(Value::Object(merged))?;
+        settings.validate()?;
+        Ok(settings)
+    }
+
+    /// Keys of any layer that are not settings, which `settings` ignores.
+    pub fn unknown_keys(&self) -> Vec<String> {
+        let known = match serde_json::to_value(Settings::default()) {
+            Ok(Value::Object(it)) => it,
+            _ => Map::new(),
+        };
+        let mut ret: Vec<String> =
+            [&self.file, &self.initialization, &self.client]
+                .into_iter()
+                .flat_map(|it| it.keys())
+                .filter(|it| !known.contains_key(*it))
+                .cloned()
+                .collect();
+        ret.sort();
+        ret.dedup();
+        ret
+    }
+}
+
+/// Keys of `.lsp-md.toml` in the first root that has one.
+pub fn read_config_file(
+    roots: &[impl AsRef<Path>],
+) -> anyhow::Result<Map<String, Value>> {
+    for root in roots {
+        let path = root.as_ref().join(CONFIG_FILE);
+        if !path.exists() {
+            continue;
+        }
+        let text = std::fs::read_to_string(&path)?;
+        return toml::from_str(&text)
+            .with_context(|| format!("invalid {}", path.display()));
+    }
+    Ok(Map::new())
+}
+
+/// Client settings as a layer. Accepts the settings themselves or an object
+/// with them under `lsp-md`, as clients push them with
+/// `workspace/didChangeConfiguration`.
+pub fn client_layer(value: Value) -> anyhow::Result<Map<String, Value>> {
+    match value {
+        Value::Null => Ok(Map::new()),
+        Value::Object(mut it) => match it.remove(CONFIG_SECTION) {
+            Some(section) => client_layer(section),
+            None => Ok(it),
+        },
+        it => Err(anyhow::anyhow!("settings must be an object, got {}", it)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn settings_should_be_overridden_by_later_layers() -> anyhow::Result<()> {
+        let dir = std::env::temp_dir()
+            .join(format!("lsp-md-settings-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        std::fs::write(
+            dir.join(CONFIG_FILE),
+            "wrapWidth = 100\nduplicateThreshold = 0.9\nmodel = \
+             \"all-MiniLM-L6-v2\"\n",
+        )?;
+
+        let layers = SettingsLayers {
+            file: read_config_file(&[&dir])?,
+            initialization: client_layer(json!({ "wrapWidth": 72 }))?,
+            client: client_layer(
+                json!({ "lsp-md": { "duplicateThreshold": 0.8 } }),
+            )?,
+        };
+        std::fs::remove_dir_all(&dir)?;
+
+        assert_eq!(
+            Settings {
+                duplicate_threshold: 0.8,
+                wrap_width: 72,
+                similar_limit: 10,
+                model: ModelType::AllMiniLmL6V2,
+                heading_pattern: None,
+            },
+            layers.settings()?
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_settings_should_be_reported() -> anyhow::Result<()> {
+        let layers = SettingsLayers {
+            client: client_layer(
+                json!({ "duplicateThreshold": 1.5, "wrapWidth": 10 }),
+            )?,
+            ..Default::default()
+        };
+        assert_eq!(
+            "duplicateThreshold must be between 0 and 1, wrapWidth must be at \
+             least 20",
+            layers.settings().unwrap_err().to_string()
+        );
+
+        let layers = SettingsLayers {
+            client: client_layer(json!({ "headingPattern": "^(" }))?,
+            ..Default::default()
+        };
+        assert_eq!(
+            "headingPattern must be a valid regex",
+            layers.settings().unwrap_err().to_string()
+        );
+
+        let layers = SettingsLayers {
+            client: client_layer(json!({ "wrapWdith": 72 }))?,
+            ..Default::default()
+        };
+        assert_eq!(Settings::default(), layers.settings()?);
+        assert_eq!(vec!["wrapWdith"], layers.unknown_keys());
+
+        Ok(())
+    }
+}